@@ -1,16 +1,101 @@
-use jeflog::fail;
-use spidev::spidevioctl::SpidevTransfer;
+//! Driver for the ADS114S0x and ADS124S0x ADCs on the SAM board.
+//!
+//! Every register written is read back and compared, and an ADC whose
+//! configuration doesn't take after three attempts is marked faulted and its
+//! channels are sent as NaN. While polling, one ADC's registers are read back
+//! each second in turn, which re-initializes any ADC that lost its
+//! configuration and gives faulted ones another try. The ID register is
+//! checked against the part in the board definition at initialization.
+//!
+//! Conversions are read with the STATUS byte and CRC. A sample with a bad CRC,
+//! or flagged in STATUS as not ready or converted against a missing reference,
+//! is rejected and counted by cause, and an ADC that reset itself is
+//! re-initialized on the spot. Conversions are read with RDATA, or shifted out
+//! directly on DRDY, optionally with the next channel's register writes in the
+//! same transfer (see `Acquisition`). With a START/SYNC pin the ADCs convert
+//! once per pulse instead of continuously.
+//!
+//! Once a second the thermocouple ADCs turn on their 1 uA burnout current
+//! sources on each thermocouple in turn. An open thermocouple is pulled to
+//! within 10% of full scale, and its readings are flagged until a later check
+//! finds it connected. One ADC a second also runs a health sweep of AVDD,
+//! DVDD, the die temperature and its reference, measured against the internal
+//! one.
+
+pub mod registers;
+
+use jeflog::{fail, pass, warn};
+use serde::{Deserialize, Serialize};
 use spidev::Spidev;
 use std::sync::Arc;
 use std::{thread, time};
 
 use std::collections::HashMap;
+use std::ops::Range;
 use std::rc::Rc;
 
-use crate::gpio::{Gpio, Pin, PinMode::{Output, Input}, PinValue::{High, Low}};
+use crate::board::Board;
+use crate::calibration::{Coefficients, Routine};
+use crate::error::SamError;
+use crate::rtd::RtdConfig;
+use crate::gpio::{write_pins, Gpio, Pin, PinGroup, PinMode, PinValue::High};
+use crate::spi::SpiTransport;
 use crate::tc::typek_convert;
+use crate::transducer::Transducer;
+use crate::transfer::Transfer;
+
+use registers::{
+    CalibrationSamples, ConversionMode, DataRate, Gain, Input, InpMux, InternalReference, Part, Pga, Ref, ReferenceMonitor, ReferenceSource, Register, SampleRate, Status,
+    Sys, SystemMonitor, ID, MAX_REGISTERS, OFCAL0, REF, STATUS, SYS,
+};
+
+// how long to wait for an ADC to assert DRDY before giving up on the sample
+pub const DEFAULT_DRDY_TIMEOUT: time::Duration = time::Duration::from_millis(5);
+
+// how many times the configuration is written before the ADC is given up on
+const INIT_ATTEMPTS: u32 = 3;
+
+// volts across the internal reference
+const REFERENCE_VOLTS: f64 = 2.5;
+
+// conversion periods to wait for DRDY, enough for a sinc3 conversion to
+// settle after a mux change
+const DRDY_PERIODS: f64 = 4.0;
+
+// conversion periods a calibration can take, averaging up to 16 samples
+const CALIBRATION_PERIODS: f64 = 20.0;
+
+// every reference is watched so a conversion against a missing one is
+// flagged in its STATUS byte
+const REF0: Ref = Ref::new().monitor(ReferenceMonitor::Threshold);
+
+// the internal 2.5 V reference, kept on between conversions
+const INTERNAL_REF: Ref = REF0
+    .negative_buffer(true)
+    .source(ReferenceSource::Internal)
+    .internal(InternalReference::AlwaysOn);
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+// conversion data is sent with the STATUS byte ahead of it and a CRC after
+const SYS_CONFIG: Sys = Sys::new().status(true).crc(true);
+
+const TEMPERATURE_SENSOR: Sys = SYS_CONFIG
+    .monitor(SystemMonitor::Temperature)
+    .calibration_samples(CalibrationSamples::One);
+
+const SYSMON_OFF: Sys = SYS_CONFIG.calibration_samples(CalibrationSamples::One);
+
+// 1 uA into AINP and out of AINN, which barely shifts an intact thermocouple
+// but drives an open one to the positive rail
+const BURNOUT: Sys = SYSMON_OFF.monitor(SystemMonitor::Burnout1);
+
+// the supply monitors read a quarter of the supply
+const SUPPLY_DIVIDER: f64 = 4.0;
+
+// readings this close to positive full scale under burnout current mean the
+// thermocouple is open
+const OPEN_CIRCUIT_FRACTION: f64 = 0.9;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum Measurement {
     CurrentLoopPt,
     VValve,
@@ -23,149 +108,680 @@ pub enum Measurement {
     Rtd
 }
 
-pub struct ADC {
+impl Measurement {
+    /// Number of channels the ADC scans through, including the cold junction
+    /// reading at the start of a thermocouple scan.
+    pub fn num_channels(&self) -> usize {
+        match self {
+            Measurement::CurrentLoopPt | Measurement::VValve | Measurement::IValve => 6,
+            Measurement::VPower => 5,
+            Measurement::IPower | Measurement::Rtd => 2,
+            Measurement::DiffSensors => 3,
+            Measurement::Tc1 | Measurement::Tc2 => 4,
+        }
+    }
+
+    /// Whether a channel reads the ADC's own temperature sensor rather than
+    /// an input, and so always runs at unity gain.
+    pub fn is_cold_junction(&self, channel: usize) -> bool {
+        matches!(self, Measurement::Tc1 | Measurement::Tc2) && channel == 0
+    }
+
+    pub fn default_channel_config(&self) -> ChannelConfig {
+        let gain = match self {
+            Measurement::CurrentLoopPt
+            | Measurement::VValve
+            | Measurement::IValve
+            | Measurement::VPower
+            | Measurement::IPower => Gain::X1,
+            Measurement::Rtd => Gain::X2,
+            Measurement::Tc1 | Measurement::Tc2 | Measurement::DiffSensors => Gain::X32,
+        };
+
+        ChannelConfig { gain, data_rate: SampleRate::Sps4000, settling: Settling::Immediate }
+    }
+}
+
+/// How conversion data is read out of an ADC while polling.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acquisition {
+    /// RDATA once the conversion is ready, then a separate WREG selecting the
+    /// next channel.
+    #[default]
+    Command,
+    /// Data shifted straight out of the ADC on DRDY, without RDATA.
+    Direct,
+    /// Direct reads, with the next channel's register writes sent in the same
+    /// transfer as the data.
+    Pipelined,
+}
+
+/// What a channel waits for once its mux is selected, before its first
+/// conversion is read, so high-impedance inputs can let go of the previous
+/// channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Settling {
+    /// Nothing beyond the ADC's own conversion latency.
+    #[default]
+    Immediate,
+    /// Reads and throws away this many conversions. ADCs read without DRDY
+    /// let each one go by for a conversion period instead.
+    Discard(u32),
+    /// Waits this many microseconds from the mux change.
+    Delay(u64),
+    /// Waits for DRDY, even on ADCs that are otherwise read without it.
+    DataReady,
+}
+
+/// Supplies, reference and die temperature of an ADC, read through its
+/// system monitor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Health {
+    pub avdd: f64,
+    pub dvdd: f64,
+    /// Volts across the reference the ADC converts against, NaN when AVDD
+    /// reads zero or below against it.
+    pub reference: f64,
+    /// Degrees Celsius.
+    pub temperature: f64,
+}
+
+/// Conversions thrown away since startup, by cause.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedSamples {
+    pub crc: u64,
+    pub status: u64,
+    pub resets: u64,
+    pub timeouts: u64,
+}
+
+impl RejectedSamples {
+    pub fn total(&self) -> u64 {
+        self.crc + self.status + self.resets + self.timeouts
+    }
+}
+
+/// CRC-8-ATM (x^8 + x^2 + x + 1) seeded with 0xFF, which the ADC appends to
+/// conversion data.
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+
+    for byte in bytes {
+        crc ^= byte;
+
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+// conversion data is two's complement, most significant byte first
+fn sign_extend(bytes: &[u8]) -> i32 {
+    let shift = 32 - 8 * bytes.len() as u32;
+    let value = bytes.iter().fold(0u32, |value, byte| value << 8 | *byte as u32);
+
+    (value << shift) as i32 >> shift
+}
+
+// the temperature sensor's output in volts to degrees Celsius
+const DIE_TEMPERATURE: Transfer = Transfer::Linear { slope: 403.0, offset: -26.987 };
+
+// divider ahead of the voltage channels
+const VOLTAGE_DIVIDER: Transfer = Transfer::Linear { slope: 11.0, offset: 0.0 };
+
+// RTD volts without a reference resistor to make ohms of, against 2.5 V
+const UNSCALED_RTD: Transfer = Transfer::Linear { slope: 0.5, offset: 0.0 };
+
+/// PGA gain and data rate a channel is converted at, and how it settles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelConfig {
+    pub gain: Gain,
+    pub data_rate: SampleRate,
+    pub settling: Settling,
+}
+
+impl ChannelConfig {
+    // unity gain bypasses the PGA, which lets single-ended inputs swing all
+    // the way to the rails
+    fn pga(&self) -> Pga {
+        match self.gain {
+            Gain::X1 => Pga::new(),
+            gain => Pga::new().gain(gain),
+        }
+    }
+
+    fn data_rate(&self, mode: ConversionMode) -> DataRate {
+        DataRate::new().mode(mode).rate(self.data_rate)
+    }
+
+    /// Volts at the ADC input per LSB.
+    fn lsb(&self, part: Part) -> f64 {
+        REFERENCE_VOLTS / part.full_scale() / self.gain.factor() as f64
+    }
+}
+
+pub struct ADC<T: SpiTransport = Spidev> {
     pub measurement: Measurement,
-    pub spidev: Rc<Spidev>,
+    pub spidev: Rc<T>,
     ambient_temp: f64,
+    drdy_timeout: time::Duration,
     gpio_mappings: Rc<HashMap<Measurement, Pin>>,
     drdy_mappings: Rc<HashMap<Measurement, Pin>>,
+    // which converter is fitted, and so how wide its data is
+    part: Part,
+    // last value written to each register since the last reset
+    written: [Option<u8>; MAX_REGISTERS],
+    faulted: bool,
+    channels: Vec<ChannelConfig>,
+    calibrations: Vec<Coefficients>,
+    // rate the ADC is converting at right now
+    data_rate: SampleRate,
+    rejected: RejectedSamples,
+    acquisition: Acquisition,
+    // register writes held back to go out with the next data read
+    queued: Option<Vec<u8>>,
+    // channel to select in the same transfer as the next data read
+    advance: Option<u64>,
+    // thermocouples found open by the last burnout check, by channel
+    open_circuit: Vec<bool>,
+    // channel the mux is set to, if any since the last reset
+    selected: Option<usize>,
+    // wiring and excitation of each channel of an RTD ADC
+    rtds: Vec<RtdConfig>,
+    // pressure transducer on each channel of a current-loop ADC, if any
+    transducers: Vec<Option<Transducer>>,
+    // transfer function of each channel, where it isn't the default
+    transfers: Vec<Option<Transfer>>,
+    // when the mux last changed, until the selected channel has settled
+    mux_changed: Option<time::Instant>,
+    // converts once per START/SYNC pulse rather than continuously
+    synchronized: bool,
+    // when the conversion the next read picks up was started
+    started: Option<time::Instant>,
 }
 
-impl ADC {
+impl<T: SpiTransport> ADC<T> {
     // Constructs a new instance of an Analog-to-Digital Converter 
-    pub fn new(measurement: Measurement, spidev: Rc<Spidev>, gpio_mappings: Rc<HashMap<Measurement, Pin>>, drdy_mappings: Rc<HashMap<Measurement, Pin>>) -> ADC {
+    pub fn new(measurement: Measurement, spidev: Rc<T>, gpio_mappings: Rc<HashMap<Measurement, Pin>>, drdy_mappings: Rc<HashMap<Measurement, Pin>>) -> ADC<T> {
         ADC {
             measurement: measurement,
             spidev: spidev,
             ambient_temp: 0.0,
+            drdy_timeout: DEFAULT_DRDY_TIMEOUT,
             gpio_mappings: gpio_mappings,
             drdy_mappings: drdy_mappings,
+            part: Part::Ads114s0x,
+            written: [None; MAX_REGISTERS],
+            faulted: false,
+            channels: vec![measurement.default_channel_config(); measurement.num_channels()],
+            calibrations: vec![Coefficients::reset(Part::Ads114s0x); measurement.num_channels()],
+            data_rate: SampleRate::Sps4000,
+            rejected: RejectedSamples::default(),
+            acquisition: Acquisition::Command,
+            queued: None,
+            advance: None,
+            open_circuit: vec![false; measurement.num_channels()],
+            selected: None,
+            rtds: (0..measurement.num_channels()).map(RtdConfig::default_for).collect(),
+            transducers: vec![None; measurement.num_channels()],
+            transfers: vec![None; measurement.num_channels()],
+            mux_changed: None,
+            synchronized: false,
+            started: None,
+        }
+    }
+
+    pub fn rejected(&self) -> RejectedSamples {
+        self.rejected
+    }
+
+    pub fn part(&self) -> Part {
+        self.part
+    }
+
+    /// Sets which converter is fitted. Every channel goes back to reset
+    /// calibration, since one part's coefficients mean nothing to the other.
+    pub fn set_part(&mut self, part: Part) {
+        self.part = part;
+        self.calibrations = vec![Coefficients::reset(part); self.channels.len()];
+    }
+
+    /// Direct and pipelined acquisition read on DRDY, so only work for ADCs
+    /// with a data ready pin.
+    pub fn set_acquisition(&mut self, acquisition: Acquisition) {
+        self.acquisition = acquisition;
+    }
+
+    /// Has the ADC convert once each time the shared START/SYNC pin pulses,
+    /// rather than continuously. Takes effect from the next `initialize`.
+    pub fn set_synchronized(&mut self, synchronized: bool) {
+        self.synchronized = synchronized;
+    }
+
+    /// Records that START/SYNC just pulsed, so a read without data ready
+    /// knows how long to give the conversion.
+    pub fn started(&mut self, at: time::Instant) {
+        self.started = Some(at);
+    }
+
+    fn conversion_mode(&self) -> ConversionMode {
+        if self.synchronized {
+            ConversionMode::SingleShot
+        } else {
+            ConversionMode::Continuous
         }
     }
 
-    pub fn cs_mappings() -> HashMap<Measurement, usize> {
-        let mut cs_gpios: HashMap<Measurement, usize> = HashMap::new();
-        cs_gpios.insert(Measurement::CurrentLoopPt, 30);
-        cs_gpios.insert(Measurement::IValve, 4);
-        cs_gpios.insert(Measurement::VValve, 26);
-        cs_gpios.insert(Measurement::VPower, 13);
-        cs_gpios.insert(Measurement::IPower, 15);
-        cs_gpios.insert(Measurement::Tc1, 10); 
-        cs_gpios.insert(Measurement::Tc2, 20);
-        cs_gpios.insert(Measurement::DiffSensors, 16); 
-        cs_gpios.insert(Measurement::Rtd, 11);
+    // a one-shot ADC only converts when told to, so anything reading it
+    // outside the scan starts its own conversion
+    fn convert_once(&mut self) {
+        if self.synchronized {
+            let _status = self.spidev.write(&[0x08]);
+            self.started = Some(time::Instant::now());
+        }
+    }
+
+    /// Sets the gain and data rate of every channel, in scan order. Takes
+    /// effect from the next time each channel is selected.
+    pub fn configure_channels(&mut self, channels: Vec<ChannelConfig>) {
+        self.channels = channels;
+    }
+
+    /// Sets the wiring and excitation of every channel of an RTD ADC, in scan
+    /// order. Takes effect from the next time each channel is selected.
+    pub fn configure_rtds(&mut self, rtds: Vec<RtdConfig>) {
+        self.rtds = rtds;
+    }
 
-        cs_gpios
+    /// Sets the pressure transducer on every channel of a current-loop ADC,
+    /// in scan order.
+    pub fn configure_transducers(&mut self, transducers: Vec<Option<Transducer>>) {
+        self.transducers = transducers;
     }
 
-    pub fn init_gpio(&mut self, prev_adc: Option<Measurement>) { 
-        // pull old adc HIGH
-        if let Some(old_adc) = prev_adc {
-            if let Some(pin) = self.gpio_mappings.get(&old_adc) {
-                pin.digital_write(High);
-            } 
+    /// Pressure transducer on the channel read at `iteration`, whose readings
+    /// are the volts across its sense resistor.
+    pub fn transducer(&self, iteration: u64) -> Option<Transducer> {
+        self.transducers[self.channel_index(iteration)]
+    }
+
+    /// Sets the transfer function of a channel, in place of the one its
+    /// hardware implies. Channels with a pressure transducer are converted by
+    /// it instead, so their raw readings stay the volts across the sense
+    /// resistor.
+    pub fn set_transfer(&mut self, channel: usize, transfer: Transfer) -> Result<(), SamError> {
+        if channel >= self.transfers.len() {
+            return Err(SamError::NoChannel { measurement: self.measurement, channel });
         }
 
-        // pull new adc LOW
-        if let Some(pin) = self.gpio_mappings.get(&self.measurement) {
-            pin.digital_write(Low);
+        if self.transducers[channel].is_some() {
+            return Err(SamError::InvalidTransfer(format!(
+                "{:?} channel {channel} has a pressure transducer, which converts it instead", self.measurement
+            )));
         }
+
+        self.transfers[channel] = Some(transfer);
+        Ok(())
     }
 
-    pub fn poll_data_ready(&mut self) {
-        // poll the data ready pin till low (active low)
+    /// Transfer function applied to a channel's readings once decoded.
+    pub fn transfer(&self, channel: usize) -> &Transfer {
+        self.transfers[channel].as_ref().unwrap_or_else(|| self.default_transfer(channel))
+    }
+
+    // what the board's hardware implies between the decoded reading and the
+    // value sent on
+    fn default_transfer(&self, channel: usize) -> &'static Transfer {
+        match self.measurement {
+            Measurement::VPower | Measurement::VValve => &VOLTAGE_DIVIDER,
+            Measurement::Tc1 | Measurement::Tc2 if self.measurement.is_cold_junction(channel) => &DIE_TEMPERATURE,
+            Measurement::Rtd if self.rtds[channel].reference_resistor.is_none() => &UNSCALED_RTD,
+            _ => &Transfer::IDENTITY,
+        }
+    }
+
+    /// Sets the offset and full-scale calibration of a channel, applied each
+    /// time the channel is selected.
+    pub fn set_calibration(&mut self, channel: usize, coefficients: Coefficients) -> Result<(), SamError> {
+        if channel >= self.calibrations.len() || self.measurement.is_cold_junction(channel) {
+            return Err(SamError::NoChannel { measurement: self.measurement, channel });
+        }
+
+        self.calibrations[channel] = coefficients;
+        Ok(())
+    }
+
+    /// Runs a calibration routine on a channel and keeps the coefficients the
+    /// ADC comes up with for that channel. Leaves the channel selected.
+    pub fn calibrate(&mut self, channel: usize, routine: Routine) -> Result<Coefficients, SamError> {
+        if channel >= self.calibrations.len() || self.measurement.is_cold_junction(channel) {
+            return Err(SamError::NoChannel { measurement: self.measurement, channel });
+        }
+
+        // calibrate on top of the channel's mux, gain, rate and coefficients
+        self.write_iteration(channel as u64);
+
+        self.spidev
+            .write(&[routine.command()])
+            .map_err(|source| SamError::AdcTransfer { measurement: self.measurement, source })?;
+
+        thread::sleep(time::Duration::from_secs_f64(CALIBRATION_PERIODS / self.data_rate.samples_per_second()));
+
+        let registers = self.read_regs()?;
+        let bytes = &registers[self.calibration_registers()];
+
+        // the ADC wrote these itself, so remember them as written
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.written[OFCAL0 as usize + offset] = Some(*byte);
+        }
+
+        let coefficients = Coefficients::from_bytes(self.part, bytes);
+        self.calibrations[channel] = coefficients;
+
+        Ok(coefficients)
+    }
+
+    /// Runs the burnout current sources through each thermocouple in turn and
+    /// flags the ones that saturate, which means a wire is broken. Leaves the
+    /// ADC back at the start of its scan.
+    pub fn check_thermocouples(&mut self) -> Result<(), SamError> {
+        if !matches!(self.measurement, Measurement::Tc1 | Measurement::Tc2) {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+
+        for channel in 1..self.channels.len() {
+            self.write_iteration(channel as u64);
+            self.write_register(BURNOUT);
+            self.convert_once();
+
+            // no data ready on the thermocouple ADCs, so wait out the settling
+            thread::sleep(time::Duration::from_secs_f64(DRDY_PERIODS / self.data_rate.samples_per_second()));
+
+            let value = match self.read_conversion() {
+                Ok(value) => value,
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            };
+
+            let open = value as f64 >= self.part.full_scale() * OPEN_CIRCUIT_FRACTION;
+
+            if open && !self.open_circuit[channel] {
+                fail!("{:?} thermocouple {} is open.", self.measurement, channel);
+            } else if !open && self.open_circuit[channel] {
+                pass!("{:?} thermocouple {} is connected again.", self.measurement, channel);
+            }
+
+            self.open_circuit[channel] = open;
+        }
+
+        // the cold junction turns the burnout currents back off
+        self.write_iteration(0);
+        result
+    }
+
+    /// Sweeps the system monitor over the supplies and temperature sensor,
+    /// and measures the ADC's reference against the internal one. Leaves the
+    /// ADC back at the start of its scan.
+    pub fn read_health(&mut self) -> Result<Health, SamError> {
+        let reference = self.written[REF as usize].unwrap_or(Ref::new().encode());
+        let sys = self.written[SYS as usize].unwrap_or(Sys::new().encode());
+
+        let health = self.sweep_monitors(reference);
+
+        self.write_reg(REF, reference);
+        self.write_reg(SYS, sys);
+        self.write_iteration(0);
+
+        health
+    }
+
+    fn sweep_monitors(&mut self, reference: u8) -> Result<Health, SamError> {
+        // every monitor is read at unity gain
+        self.write_register(Pga::new());
+
+        let volts = REFERENCE_VOLTS / self.part.full_scale();
+        let internal = INTERNAL_REF.encode();
+
+        let avdd = self.read_monitor(SystemMonitor::Avdd, internal)?;
+        let dvdd = self.read_monitor(SystemMonitor::Dvdd, internal)?;
+        let temperature = self.read_monitor(SystemMonitor::Temperature, internal)?;
+
+        // the same AVDD reading against the ADC's own reference scales with it
+        let against_reference = self.read_monitor(SystemMonitor::Avdd, reference)?;
+
+        // AVDD only reads zero or below against a missing or shorted
+        // reference, which has no meaningful voltage to report
+        let reference_volts = if against_reference > 0 {
+            REFERENCE_VOLTS * avdd as f64 / against_reference as f64
+        } else {
+            warn!("{:?} ADC read AVDD as {} against its reference, which looks missing.", self.measurement, against_reference);
+            f64::NAN
+        };
+
+        Ok(Health {
+            avdd: avdd as f64 * volts * SUPPLY_DIVIDER,
+            dvdd: dvdd as f64 * volts * SUPPLY_DIVIDER,
+            reference: reference_volts,
+            temperature: DIE_TEMPERATURE.apply(temperature as f64 * volts),
+        })
+    }
+
+    fn read_monitor(&mut self, monitor: SystemMonitor, reference: u8) -> Result<i32, SamError> {
+        self.write_reg(REF, reference);
+        self.write_register(SYS_CONFIG.monitor(monitor).calibration_samples(CalibrationSamples::One));
+        self.convert_once();
+
+        // not every ADC has data ready, so wait out the settling
+        thread::sleep(time::Duration::from_secs_f64(DRDY_PERIODS / self.data_rate.samples_per_second()));
+
+        self.read_conversion()
+    }
+
+    /// Whether the last burnout check found the thermocouple on this channel
+    /// open.
+    pub fn is_open_circuit(&self, iteration: u64) -> bool {
+        let channel = (iteration % self.open_circuit.len() as u64) as usize;
+        self.open_circuit[channel]
+    }
+
+    /// What the last burnout check found on each channel, open or not.
+    pub fn open_circuit(&self) -> &[bool] {
+        &self.open_circuit
+    }
+
+    fn channel(&self, iteration: u64) -> ChannelConfig {
+        self.channels[self.channel_index(iteration)]
+    }
+
+    fn channel_index(&self, iteration: u64) -> usize {
+        (iteration % self.channels.len() as u64) as usize
+    }
+
+    pub fn init_gpio(&mut self, prev_adc: Option<Measurement>) -> Result<(), SamError> {
+        // pull old adc HIGH and new adc LOW, together if they share a bank
+        let old_pin = prev_adc.and_then(|old_adc| self.gpio_mappings.get(&old_adc));
+        let new_pin = self.gpio_mappings.get(&self.measurement);
+
+        write_pins(old_pin.as_slice(), new_pin.as_slice())
+    }
+
+    pub fn set_drdy_timeout(&mut self, timeout: time::Duration) {
+        self.drdy_timeout = timeout;
+    }
+
+    pub fn poll_data_ready(&mut self) -> Result<(), SamError> {
+        // wait for the data ready pin to go low (active low)
         let drdy_pin = self.drdy_mappings.get(&self.measurement).unwrap();
 
+        // slow data rates need longer than the default to convert
+        let period = time::Duration::from_secs_f64(DRDY_PERIODS / self.data_rate.samples_per_second());
+        let timeout = self.drdy_timeout.max(period);
+
+        let asserted = drdy_pin
+            .wait_for_low(timeout)
+            .map_err(|source| SamError::DataReady { measurement: self.measurement, source })?;
+
+        if asserted {
+            Ok(())
+        } else {
+            Err(SamError::DataReadyTimeout { measurement: self.measurement, timeout })
+        }
+    }
+
+    /// True once the ADC has failed to take its configuration. Cleared by the
+    /// next successful `init_regs`.
+    pub fn is_faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Resets the ADC, configures it and leaves it converting the first
+    /// channel of the scan.
+    pub fn initialize(&mut self) -> Result<(), SamError> {
+        self.reset_status();
+        self.init_regs()?;
+        self.start_conversion();
+        self.write_iteration(0);
+
+        Ok(())
+    }
+
+    /// Writes the configuration and reads it back, rewriting it up to
+    /// INIT_ATTEMPTS times before marking the ADC faulted.
+    pub fn init_regs(&mut self) -> Result<(), SamError> {
+        let mut attempt = 1;
+
         loop {
-            let pin_value = drdy_pin.digital_read();
-            if pin_value == Low {
-                break;
+            self.write_config();
+
+            match self.verify_regs() {
+                Ok(()) => {
+                    self.faulted = false;
+                    return Ok(());
+                }
+                Err(error) if attempt < INIT_ATTEMPTS => {
+                    warn!("{:?} ADC configuration did not take ({}), rewriting.", self.measurement, error);
+                    attempt += 1;
+                }
+                Err(error) => {
+                    self.faulted = true;
+                    return Err(error);
+                }
             }
         }
     }
 
-    pub fn init_regs(&mut self) {
-        // Read initial registers
-        self.read_regs(0, 17);
+    fn write_config(&mut self) {
+        let channel = self.channels[0];
+        self.data_rate = channel.data_rate;
 
-        // delay for at least 4000*clock period
-        // println!("Delaying for 1 second");
-        //thread::sleep(time::Duration::from_millis(100));
+        // clear the power-on reset flag, so seeing it again means the ADC
+        // reset since
+        self.write_reg(STATUS, 0x00);
+        self.write_register(SYS_CONFIG);
 
-        // Write to registers
         match self.measurement {
             Measurement::CurrentLoopPt | 
             Measurement::VPower |
             Measurement::IPower |
             Measurement::IValve |
             Measurement::VValve => {
-                self.write_reg(0x03, 0x00);
-                self.write_reg(0x04, 0x1E);
-                // self.write_reg(0x08, 0x40);
-                // self.write_reg(0x08, 0x00);
-                self.write_reg(0x05, 0x0A);
+                self.write_register(channel.pga());
+                self.write_register(channel.data_rate(self.conversion_mode()));
+                self.write_register(INTERNAL_REF);
             }
 
             Measurement::Rtd => {
-                self.write_reg(0x03, 0x09);
-                self.write_reg(0x04, 0x1E);
-                // self.write_reg(0x06, 0x47);
-                self.write_reg(0x06, 0x07);
-                self.write_reg(0x07, 0x05);
+                let rtd = self.rtds[0];
+                self.write_register(channel.pga());
+                self.write_register(channel.data_rate(self.conversion_mode()));
+                self.write_register(rtd.idac_mag());
+                self.write_register(rtd.idac_mux());
             }
 
             Measurement::Tc1 | 
             Measurement::Tc2 | 
             Measurement::DiffSensors => {
-                self.write_reg(0x03, 0x0D);
-                self.write_reg(0x04, 0x1E);
-                self.write_reg(0x05, 0x0A);
+                self.write_register(channel.pga());
+                self.write_register(channel.data_rate(self.conversion_mode()));
+                self.write_register(INTERNAL_REF);
             }
         }
 
-        // delay for at least 4000*clock period
-        // println!("Delaying for 1 second");
-        //thread::sleep(time::Duration::from_millis(100));
+        self.update_calibration(self.calibrations[0]);
+    }
+
+    /// Checks every register written since the last reset against what the
+    /// ADC reports, catching ADCs that were reset by a brownout.
+    pub fn verify_regs(&mut self) -> Result<(), SamError> {
+        let actual = self.read_regs()?;
 
-        // Read registers
-        self.read_regs(0, 17);
+        // the other part's data would be misread, so don't go any further
+        if !self.part.matches_id(actual[ID as usize]) {
+            return Err(SamError::AdcPart { measurement: self.measurement, part: self.part, id: actual[ID as usize] });
+        }
 
+        for (register, (expected, actual)) in self.written.iter().zip(actual).enumerate() {
+            // STATUS holds flags, not configuration
+            if register == STATUS as usize {
+                continue;
+            }
+
+            match expected {
+                Some(expected) if *expected != actual => {
+                    return Err(SamError::AdcRegister {
+                        measurement: self.measurement,
+                        register: register as u8,
+                        expected: *expected,
+                        actual,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
     }
     
     pub fn reset_status(&mut self) {
         let tx_buf_reset = [0x06];
-        let mut transfer = SpidevTransfer::write(&tx_buf_reset);
-        let _status = self.spidev.transfer(&mut transfer);
+        let _status = self.spidev.write(&tx_buf_reset);
+
+        // every register is back at its reset value
+        self.written = [None; MAX_REGISTERS];
+        self.selected = None;
+        self.mux_changed = None;
+        self.started = None;
     }
 
     pub fn start_conversion(&mut self) {
-        let mut tx_buf_rdata = [ 0x08];
+        let tx_buf_rdata = [ 0x08];
         let mut rx_buf_rdata = [ 0x00];
-        let mut transfer = SpidevTransfer::read_write(&mut tx_buf_rdata, &mut rx_buf_rdata);
-        let _status = self.spidev.transfer(&mut transfer);
+        let _status = self.spidev.transfer(&tx_buf_rdata, &mut rx_buf_rdata);
         thread::sleep(time::Duration::from_millis(1));
     }
 
-    pub fn self_calibrate(&mut self) {
-        let mut tx_buf_rdata = [ 0x19];
-        let mut rx_buf_rdata = [ 0x00];
-        let mut transfer = SpidevTransfer::read_write(&mut tx_buf_rdata, &mut rx_buf_rdata);
-        let _status = self.spidev.transfer(&mut transfer);
-        thread::sleep(time::Duration::from_millis(1000));
-    }
     
-    pub fn read_regs(&mut self, reg: u8, num_regs: u8) {
-        let mut tx_buf_readreg = [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00 ];
-        let mut rx_buf_readreg = [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00 ];
-        tx_buf_readreg[0] = 0x20 | reg;
-        tx_buf_readreg[1] = num_regs;
-        let mut transfer = SpidevTransfer::read_write(&mut tx_buf_readreg, &mut rx_buf_readreg);
-        let _status = self.spidev.transfer(&mut transfer);
-        
-        println!("{:?} regs: {:?}", self.measurement, rx_buf_readreg);
-        if rx_buf_readreg == [ 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00 ] {
-            fail!("Failed to write and read correct register values");
-        }
+    /// Reads every register of the part in one RREG.
+    pub fn read_regs(&mut self) -> Result<Vec<u8>, SamError> {
+        let count = self.part.num_registers();
+        let mut tx_buf_readreg = vec![0x00; count + 2];
+        let mut rx_buf_readreg = vec![0x00; count + 2];
+        tx_buf_readreg[0] = 0x20 | ID;
+        tx_buf_readreg[1] = (count - 1) as u8;
+
+        self.spidev
+            .transfer(&tx_buf_readreg, &mut rx_buf_readreg)
+            .map_err(|source| SamError::AdcTransfer { measurement: self.measurement, source })?;
+
+        Ok(rx_buf_readreg.split_off(2))
     }
     
     pub fn write_reg(&mut self, reg: u8, data: u8) {
@@ -173,40 +789,221 @@ impl ADC {
         let mut rx_buf_writereg = [ 0x40, 0x00, 0x00 ];
         tx_buf_writereg[0] = 0x40 | reg;
         tx_buf_writereg[2] = data;
-        let mut transfer = SpidevTransfer::read_write(&mut tx_buf_writereg, &mut rx_buf_writereg);
-        let _status = self.spidev.transfer(&mut transfer);
+
+        if let Some(queued) = self.queued.as_mut() {
+            queued.extend_from_slice(&tx_buf_writereg);
+        } else {
+            let _status = self.spidev.transfer(&tx_buf_writereg, &mut rx_buf_writereg);
+        }
+
+        if let Some(written) = self.written.get_mut(reg as usize) {
+            *written = Some(data);
+        }
     }
 
-    pub fn get_adc_reading(&mut self, iteration: u64) -> (f64, f64) {
-        if  self.measurement == Measurement::Rtd || 
-            self.measurement == Measurement::Tc1 || 
-            self.measurement == Measurement::Tc2 {
-                // can't use data ready for these
-                // thread::sleep(time::Duration::from_micros(700));
+    pub fn write_register<R: Register>(&mut self, register: R) {
+        self.write_reg(R::ADDRESS, register.encode());
+    }
+
+    // skips the write when the register already holds the value
+    fn update_register<R: Register>(&mut self, register: R) {
+        if self.written[R::ADDRESS as usize] != Some(register.encode()) {
+            self.write_register(register);
+        }
+    }
+
+    // writes OFCAL and FSCAL together, unless they already hold the
+    // coefficients (either written or from reset)
+    fn update_calibration(&mut self, coefficients: Coefficients) {
+        let current = &self.written[self.calibration_registers()];
+        let reset = Coefficients::reset(self.part).to_bytes(self.part);
+        let bytes = coefficients.to_bytes(self.part);
+
+        let unchanged = current
+            .iter()
+            .zip(reset)
+            .zip(&bytes)
+            .all(|((current, reset), byte)| current.unwrap_or(reset) == *byte);
+
+        if !unchanged {
+            self.write_regs(OFCAL0, &bytes);
+        }
+    }
+
+    // OFCAL then FSCAL, two or three bytes each
+    fn calibration_registers(&self) -> Range<usize> {
+        OFCAL0 as usize..OFCAL0 as usize + 2 * self.part.word_bytes()
+    }
+
+    /// Writes consecutive registers starting at `reg` in one WREG.
+    pub fn write_regs(&mut self, reg: u8, data: &[u8]) {
+        let mut tx_buf_writereg = vec![0x40 | reg, (data.len() - 1) as u8];
+        tx_buf_writereg.extend_from_slice(data);
+
+        if let Some(queued) = self.queued.as_mut() {
+            queued.extend_from_slice(&tx_buf_writereg);
+        } else {
+            let mut rx_buf_writereg = vec![0x00; tx_buf_writereg.len()];
+            let _status = self.spidev.transfer(&tx_buf_writereg, &mut rx_buf_writereg);
+        }
+
+        for (offset, byte) in data.iter().enumerate() {
+            if let Some(written) = self.written.get_mut(reg as usize + offset) {
+                *written = Some(*byte);
             }
-        else {
-            self.poll_data_ready();
         }
-        let val = self.test_read_individual(iteration).try_into().unwrap();
+    }
+
+    /// Reads a channel and selects the one read after it, together when the
+    /// ADC is pipelined.
+    pub fn read_and_advance(&mut self, iteration: u64, next: u64) -> Result<(f64, f64), SamError> {
+        let pipelined = self.acquisition == Acquisition::Pipelined;
+
+        // something else, e.g. a calibration, left another channel selected
+        if self.selected != Some(self.channel_index(iteration)) {
+            self.write_iteration(iteration);
+        }
+
+        if pipelined {
+            self.advance = Some(next);
+        }
+
+        let reading = self.get_adc_reading(iteration);
+
+        // a pipelined read that never happened didn't select anything
+        if !pipelined || self.advance.take().is_some() {
+            self.write_iteration(next);
+        }
+
+        reading
+    }
+
+    pub fn get_adc_reading(&mut self, iteration: u64) -> Result<(f64, f64), SamError> {
+        self.settle(iteration)?;
+
+        let val = match self.wait_for_conversion().and_then(|()| self.test_read_individual(iteration)) {
+            Ok(val) => val,
+            Err(error) => {
+                self.count_rejected(&error);
+                return Err(error);
+            }
+        };
         
         // let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         // let unix_timestamp = start.as_secs_f64();
 
         let unix_timestamp = 0.0; // change this! 
 
-        (val, unix_timestamp)
+        Ok((val, unix_timestamp))
+    }
+
+    // the RTD and thermocouple ADCs have no data ready to wait on, unless
+    // they are read directly
+    fn waits_for_data_ready(&self) -> bool {
+        self.acquisition != Acquisition::Command
+            || !matches!(self.measurement, Measurement::Rtd | Measurement::Tc1 | Measurement::Tc2)
+    }
+
+    fn count_rejected(&mut self, error: &SamError) {
+        match error {
+            SamError::DataReadyTimeout { .. } | SamError::DataReady { .. } => self.rejected.timeouts += 1,
+            SamError::AdcCrc { .. } => self.rejected.crc += 1,
+            SamError::AdcStatus { .. } => self.rejected.status += 1,
+            SamError::AdcReset { .. } => self.rejected.resets += 1,
+            _ => {}
+        }
+    }
+
+    // waits for the conversion a read picks up: DRDY where there is one, and
+    // the conversion time after a START/SYNC pulse where there isn't
+    fn wait_for_conversion(&mut self) -> Result<(), SamError> {
+        if self.waits_for_data_ready() {
+            return self.poll_data_ready();
+        }
+
+        if let Some(started) = self.started.take() {
+            let conversion = time::Duration::from_secs_f64(DRDY_PERIODS / self.data_rate.samples_per_second());
+            thread::sleep(conversion.saturating_sub(started.elapsed()));
+        }
+
+        Ok(())
+    }
+
+    /// Holds off the first read of a channel after its mux changed until the
+    /// channel's settling is done. Reads settle by themselves, but ADCs
+    /// converting on START/SYNC have to settle before the pulse.
+    pub fn settle(&mut self, iteration: u64) -> Result<(), SamError> {
+        let result = self.settle_channel(iteration);
+
+        if let Err(error) = &result {
+            self.count_rejected(error);
+        }
+
+        result
+    }
+
+    fn settle_channel(&mut self, iteration: u64) -> Result<(), SamError> {
+        let Some(changed) = self.mux_changed.take() else {
+            return Ok(());
+        };
+
+        match self.channel(iteration).settling {
+            Settling::Immediate => Ok(()),
+            Settling::Delay(micros) => {
+                thread::sleep(time::Duration::from_micros(micros).saturating_sub(changed.elapsed()));
+                Ok(())
+            }
+            Settling::DataReady => {
+                self.convert_once();
+                self.poll_data_ready()
+            }
+            Settling::Discard(count) => {
+                // the discarded reads mustn't select the next channel
+                let advance = self.advance.take();
+                let result = (0..count).try_for_each(|_| self.discard_conversion());
+                self.advance = advance;
+
+                result
+            }
+        }
+    }
+
+    fn discard_conversion(&mut self) -> Result<(), SamError> {
+        self.convert_once();
+
+        if self.synchronized || self.waits_for_data_ready() {
+            self.wait_for_conversion()?;
+            self.read_conversion().map(drop)
+        } else {
+            thread::sleep(time::Duration::from_secs_f64(1.0 / self.data_rate.samples_per_second()));
+            Ok(())
+        }
     }
 
     pub fn write_iteration(&mut self, iteration: u64) {
+        let channel = self.channel_index(iteration);
+        self.selected = Some(channel);
+        self.mux_changed = Some(time::Instant::now());
+
+        if !self.measurement.is_cold_junction(channel) {
+            let config = self.channels[channel];
+            self.data_rate = config.data_rate;
+            self.update_register(config.pga());
+            self.update_register(config.data_rate(self.conversion_mode()));
+        }
+
+        // the cold junction always runs uncalibrated
+        self.update_calibration(self.calibrations[channel]);
+
         match self.measurement {
             Measurement::CurrentLoopPt => {
                 match iteration % 6 {
-                    0 => { self.write_reg(0x02, 0x00 | 0x0C); }
-                    1 => { self.write_reg(0x02, 0x10 | 0x0C); }
-                    2 => { self.write_reg(0x02, 0x20 | 0x0C); }
-                    3 => { self.write_reg(0x02, 0x30 | 0x0C); }
-                    4 => { self.write_reg(0x02, 0x40 | 0x0C); }
-                    5 => { self.write_reg(0x02, 0x50 | 0x0C); }
+                    0 => { self.write_register(InpMux::new(Input::Ain0, Input::AinCom)); }
+                    1 => { self.write_register(InpMux::new(Input::Ain1, Input::AinCom)); }
+                    2 => { self.write_register(InpMux::new(Input::Ain2, Input::AinCom)); }
+                    3 => { self.write_register(InpMux::new(Input::Ain3, Input::AinCom)); }
+                    4 => { self.write_register(InpMux::new(Input::Ain4, Input::AinCom)); }
+                    5 => { self.write_register(InpMux::new(Input::Ain5, Input::AinCom)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
@@ -214,46 +1011,47 @@ impl ADC {
             Measurement::IValve |
             Measurement::VValve => {
                 match iteration % 6 {
-                    0 => { self.write_reg(0x02, 0x50 | 0x0C); }
-                    1 => { self.write_reg(0x02, 0x40 | 0x0C); }
-                    2 => { self.write_reg(0x02, 0x30 | 0x0C); }
-                    3 => { self.write_reg(0x02, 0x20 | 0x0C); }
-                    4 => { self.write_reg(0x02, 0x10 | 0x0C); }
-                    5 => { self.write_reg(0x02, 0x00 | 0x0C); }
+                    0 => { self.write_register(InpMux::new(Input::Ain5, Input::AinCom)); }
+                    1 => { self.write_register(InpMux::new(Input::Ain4, Input::AinCom)); }
+                    2 => { self.write_register(InpMux::new(Input::Ain3, Input::AinCom)); }
+                    3 => { self.write_register(InpMux::new(Input::Ain2, Input::AinCom)); }
+                    4 => { self.write_register(InpMux::new(Input::Ain1, Input::AinCom)); }
+                    5 => { self.write_register(InpMux::new(Input::Ain0, Input::AinCom)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
 
             Measurement::VPower => {
                 match iteration % 5 {
-                    0 => { self.write_reg(0x02, 0x00 | 0x0C); }
-                    1 => { self.write_reg(0x02, 0x10 | 0x0C); }
-                    2 => { self.write_reg(0x02, 0x20 | 0x0C); }
-                    3 => { self.write_reg(0x02, 0x30 | 0x0C); }
-                    4 => { self.write_reg(0x02, 0x40 | 0x0C); }
+                    0 => { self.write_register(InpMux::new(Input::Ain0, Input::AinCom)); }
+                    1 => { self.write_register(InpMux::new(Input::Ain1, Input::AinCom)); }
+                    2 => { self.write_register(InpMux::new(Input::Ain2, Input::AinCom)); }
+                    3 => { self.write_register(InpMux::new(Input::Ain3, Input::AinCom)); }
+                    4 => { self.write_register(InpMux::new(Input::Ain4, Input::AinCom)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
             Measurement::IPower => {
                 match iteration % 2 {
-                    0 => { self.write_reg(0x02, 0x00 | 0x0C); }
-                    1 => { self.write_reg(0x02, 0x10 | 0x0C); }
+                    0 => { self.write_register(InpMux::new(Input::Ain0, Input::AinCom)); }
+                    1 => { self.write_register(InpMux::new(Input::Ain1, Input::AinCom)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
             Measurement::Rtd => {
-                match iteration % 2 {
-                    0 => { self.write_reg(0x02, 0x12); self.write_reg(0x05, 0x10); } 
-                    1 => { self.write_reg(0x02, 0x34); self.write_reg(0x05, 0x14); } 
-                    _ => fail!("Failed register write — could not mod iteration")
-                }
+                // excitation first, so the reference is there when the mux is
+                let rtd = self.rtds[channel];
+                self.update_register(rtd.idac_mag());
+                self.update_register(rtd.idac_mux());
+                self.write_register(rtd.inpmux());
+                self.write_register(rtd.reference());
             }
 
             Measurement::DiffSensors => {
                 match iteration % 3 {
-                    0 => { self.write_reg(0x02, 0x54); }
-                    1 => { self.write_reg(0x02, 0x32); }
-                    2 => { self.write_reg(0x02, 0x10); }
+                    0 => { self.write_register(InpMux::new(Input::Ain5, Input::Ain4)); }
+                    1 => { self.write_register(InpMux::new(Input::Ain3, Input::Ain2)); }
+                    2 => { self.write_register(InpMux::new(Input::Ain1, Input::Ain0)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
@@ -261,162 +1059,190 @@ impl ADC {
             Measurement::Tc1 |
             Measurement::Tc2 => {
                 match iteration % 4 {
-                    0 => { self.write_reg(0x03, 0x08); self.write_reg(0x09, 0x40); }
-                    1 => { self.write_reg(0x02, 0x50 | 0x04); }
-                    2 => { self.write_reg(0x02, 0x30 | 0x02); }
-                    3 => { self.write_reg(0x02, 0x10 | 0x00); }
+                    0 => { self.write_register(Pga::new().gain(Gain::X1)); self.write_register(TEMPERATURE_SENSOR); }
+                    1 => { self.update_register(SYSMON_OFF); self.write_register(InpMux::new(Input::Ain5, Input::Ain4)); }
+                    2 => { self.update_register(SYSMON_OFF); self.write_register(InpMux::new(Input::Ain3, Input::Ain2)); }
+                    3 => { self.update_register(SYSMON_OFF); self.write_register(InpMux::new(Input::Ain1, Input::Ain0)); }
                     _ => fail!("Failed register write — could not mod iteration")
                 }
             }
         }
     }
 
-    pub fn test_read_individual(&mut self, iteration: u64) -> f64 {
-        let mut tx_buf_rdata = [ 0x12, 0x00, 0x00 ];
-        let mut rx_buf_rdata = [ 0x00, 0x00, 0x00 ];
-        let mut transfer = SpidevTransfer::read_write(&mut tx_buf_rdata, &mut rx_buf_rdata);
-        let _status = self.spidev.transfer(&mut transfer);
-        let value: i16 = ((rx_buf_rdata[1] as i16) << 8) | (rx_buf_rdata[2] as i16);
-        let value2: f64 = value as f64;
+    /// Reads the latest conversion, checking its STATUS byte and CRC.
+    pub fn read_conversion(&mut self) -> Result<i32, SamError> {
+        // RDATA, then STATUS, two or three data bytes and the CRC. Read
+        // directly, the ADC shifts them out without a command as long as DIN
+        // stays low
+        let mut tx_buf_rdata = match self.acquisition {
+            Acquisition::Command => vec![ 0x12 ],
+            Acquisition::Direct | Acquisition::Pipelined => vec![],
+        };
+        let start = tx_buf_rdata.len();
+        let length = self.part.word_bytes() + 2;
+        tx_buf_rdata.resize(start + length, 0x00);
 
-        let mut reading = value2;
+        // the next channel's writes follow the data, which was converted
+        // before they restart the conversion
+        if let Some(next) = self.advance.take() {
+            self.queued = Some(Vec::new());
+            self.write_iteration(next);
+            tx_buf_rdata.extend(self.queued.take().unwrap_or_default());
+        }
 
-        match self.measurement {
-            Measurement::CurrentLoopPt | Measurement::IValve => {
-                reading = ((value as i32 + 32768) as f64) * (2.5 / ((1 << 15) as f64));
-                //println!("valve {:?} I: {:?}", (iteration % 6) + 1, reading);
-            }
-            Measurement::VPower | Measurement::VValve => {
-                reading = ((value as i32 + 32768) as f64) * (2.5 / ((1 << 15) as f64)) * 11.0; // 0 ref
-                // println!("{:?}: {:?}", (iteration % 5) + 1, reading);
-                //println!("valve {:?} V: {:?}", (iteration % 6) + 1, reading);
+        let mut rx_buf_rdata = vec![0x00; tx_buf_rdata.len()];
+        self.spidev
+            .transfer(&tx_buf_rdata, &mut rx_buf_rdata)
+            .map_err(|source| SamError::AdcTransfer { measurement: self.measurement, source })?;
+
+        let (frame, crc) = rx_buf_rdata[start..start + length].split_at(length - 1);
+
+        // check the CRC first, a corrupted STATUS byte means nothing
+        let expected = crc8(frame);
+        let actual = crc[0];
+
+        if expected != actual {
+            // a reset turns STATUS and CRC back off, so the data that follows
+            // one never checks out
+            let sys = self.read_regs()?[SYS as usize];
+
+            if Some(sys) != self.written[SYS as usize] {
+                return Err(SamError::AdcReset { measurement: self.measurement });
             }
-            Measurement::IPower => {
-                reading = ((value as i32 + 32768) as f64) * (2.5 / ((1 << 15) as f64)); // 2.5 ref
-                // println!("{:?}: {:?}", (iteration % 2) + 1, reading);
+
+            return Err(SamError::AdcCrc { measurement: self.measurement, expected, actual });
+        }
+
+        let status = Status(frame[0]);
+
+        if status.power_on_reset() {
+            return Err(SamError::AdcReset { measurement: self.measurement });
+        }
+
+        if status.not_ready() || status.reference_low() {
+            return Err(SamError::AdcStatus { measurement: self.measurement, status: status.0 });
+        }
+
+        Ok(sign_extend(&frame[1..]))
+    }
+
+    /// Reads the conversion of the channel at `iteration`, decodes it into
+    /// what the channel measures (volts, RTD ohms or thermocouple kelvin), and
+    /// applies the channel's transfer function.
+    pub fn test_read_individual(&mut self, iteration: u64) -> Result<f64, SamError> {
+        let value = self.read_conversion()? as f64;
+        let channel = self.channel_index(iteration);
+        let lsb = self.channel(iteration).lsb(self.part);
+        let full_scale = self.part.full_scale();
+
+        let decoded = match self.measurement {
+            // single-ended against a 0 V reference
+            Measurement::CurrentLoopPt | Measurement::IValve | Measurement::VPower | Measurement::VValve | Measurement::IPower => {
+                (value + full_scale) * lsb
             }
             Measurement::Rtd => {
-                reading = (value as f64) * (2.5 / ((1 << 15) as f64)) / 4.0; // 2.5 ref
-                // println!("{:?}: {:?}", (iteration % 2) + 1, reading);
+                // ohms when the reference resistor is known, ratiometric
+                let fraction = value * lsb / REFERENCE_VOLTS;
+                self.rtds[channel].resistance(fraction).unwrap_or(value * lsb)
             }
-            Measurement::Tc1 | Measurement::Tc2 => {
-                if iteration % 4 == 0 {
-                    // ambient temp 
-                    reading = ((value as i32) as f64) * (2.5 / ((1 << 15) as f64)) * 1000.0;
-                    let ambient = reading * 0.403 - 26.987;
-                    self.ambient_temp = ambient;
-                    self.write_reg(0x09, 0x0); // reset sysmon
-                    self.write_reg(0x03, 0x0D); // reset PGA gain
-                } else {
-                    // convert
-                    reading = (value as f64) * (2.5 / ((1 << 15) as f64)) / 0.032; // gain of 32
-                    reading = (typek_convert(self.ambient_temp as f32, reading as f32) + 273.15) as f64;
-                }
+            // always read at unity gain
+            Measurement::Tc1 | Measurement::Tc2 if self.measurement.is_cold_junction(channel) => {
+                value * REFERENCE_VOLTS / full_scale
             }
-            Measurement::DiffSensors => {
-                reading = ((value as f64) * (2.5 / ((1 << 15) as f64)) / 0.032) / 1000.0; // gain of 32
-                // println!("{:?}: {:?}", (iteration % 3) + 1, reading);
+            Measurement::Tc1 | Measurement::Tc2 => {
+                let millivolts = value * lsb * 1000.0;
+                (typek_convert(self.ambient_temp as f32, millivolts as f32) + 273.15) as f64
             }
+            Measurement::DiffSensors => value * lsb,
+        };
+
+        let reading = self.transfer(channel).apply(decoded);
+
+        if self.measurement.is_cold_junction(channel) {
+            self.ambient_temp = reading;
         }
-        reading
+
+        Ok(reading)
     }
 }
 
-pub fn open_controllers() -> Vec<Arc<Gpio>> {
-    (0..=3).map(|i| Gpio::open(i)).collect()
+pub fn open_controllers() -> Result<Vec<Arc<Gpio>>, SamError> {
+    // SAM_GPIO_BACKEND=chardev drives pins through /dev/gpiochipN instead of
+    // /dev/mem, and =memory swaps in in-memory registers so SAM can run off
+    // the BeagleBone
+    match std::env::var("SAM_GPIO_BACKEND").as_deref() {
+        Ok("chardev") => (0..=3).map(Gpio::open_chardev).collect(),
+        Ok("memory") => Ok((0..=3).map(|_| Gpio::in_memory()).collect()),
+        _ => (0..=3).map(Gpio::open).collect(),
+    }
 }
 
-pub fn gpio_controller_mappings(controllers: &Vec<Arc<Gpio>>) -> HashMap<Measurement, Pin> {
-    let cl_pin = controllers[0].get_pin(30);
-    cl_pin.mode(Output);
-
-    let i_valve_pin = controllers[2].get_pin(4);
-    i_valve_pin.mode(Output);
-
-    let v_valve_pin = controllers[0].get_pin(26);
-    v_valve_pin.mode(Output);
-
-    let v_power_pin = controllers[2].get_pin(13);
-    v_power_pin.mode(Output);
+pub fn gpio_controller_mappings(controllers: &[Arc<Gpio>], board: &Board) -> Result<HashMap<Measurement, Pin>, SamError> {
+    let mut gpio_mapping = HashMap::new();
 
-    let i_power_pin = controllers[2].get_pin(15);
-    i_power_pin.mode(Output);
-
-    let tc_1_pin = controllers[0].get_pin(10);
-    tc_1_pin.mode(Output);
-
-    let tc_2_pin = controllers[0].get_pin(20);
-    tc_2_pin.mode(Output);
+    for adc in &board.adcs {
+        let cs_pin = adc.cs.get(controllers);
+        cs_pin.mode(PinMode::Output)?;
+        gpio_mapping.insert(adc.measurement, cs_pin);
+    }
 
-    let diff_pin = controllers[3].get_pin(16);
-    diff_pin.mode(Output);
+    Ok(gpio_mapping)
+}
 
-    let rtd_pin = controllers[2].get_pin(11);
-    rtd_pin.mode(Output);
+pub fn data_ready_mappings(controllers: &[Arc<Gpio>], board: &Board) -> Result<HashMap<Measurement, Pin>, SamError> {
+    let mut gpio_mapping = HashMap::new();
 
-    let gpio_mapping = HashMap::from([
-        (Measurement::CurrentLoopPt, cl_pin),
-        (Measurement::IValve, i_valve_pin),
-        (Measurement::VValve, v_valve_pin),
-        (Measurement::VPower, v_power_pin),
-        (Measurement::IPower, i_power_pin),
-        (Measurement::Tc1, tc_1_pin),
-        (Measurement::Tc2, tc_2_pin),
-        (Measurement::DiffSensors, diff_pin),
-        (Measurement::Rtd, rtd_pin),
-        ]);
+    for adc in &board.adcs {
+        if let Some(drdy) = adc.drdy {
+            let drdy_pin = drdy.get(controllers);
+            drdy_pin.mode(PinMode::Input)?;
+            gpio_mapping.insert(adc.measurement, drdy_pin);
+        }
+    }
 
-    gpio_mapping
+    Ok(gpio_mapping)
 }
 
-pub fn data_ready_mappings(controllers: &Vec<Arc<Gpio>>) -> HashMap<Measurement, Pin> {
-    let cl_pin = controllers[1].get_pin(28);
-    cl_pin.mode(Input);
-
-    let i_valve_pin = controllers[2].get_pin(3);
-    i_valve_pin.mode(Input);
+pub fn pull_gpios_high(controllers: &[Arc<Gpio>], board: &Board) -> Result<(), SamError> {
+    let pins: Vec<Pin> = board.chip_selects().map(|cs| cs.get(controllers)).collect();
+    let chip_selects = PinGroup::new(&pins);
 
-    let v_valve_pin = controllers[1].get_pin(12);
-    v_valve_pin.mode(Input);
+    chip_selects.mode(PinMode::Output)?;
+    chip_selects.digital_write(High)
+}
 
-    let v_power_pin = controllers[2].get_pin(12);
-    v_power_pin.mode(Input);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let i_power_pin = controllers[2].get_pin(14);
-    i_power_pin.mode(Input);
+    #[test]
+    fn crc8_covers_status_and_16_bit_data() {
+        assert_eq!(crc8(&[0x40, 0x12, 0x34]), 0x5C);
+    }
 
-    let diff_pin = controllers[3].get_pin(15);
-    diff_pin.mode(Input);
+    #[test]
+    fn crc8_covers_status_and_24_bit_data() {
+        assert_eq!(crc8(&[0x40, 0x12, 0x34, 0x56]), 0x36);
+    }
 
-    let gpio_mapping = HashMap::from([
-        (Measurement::CurrentLoopPt, cl_pin),
-        (Measurement::IValve, i_valve_pin),
-        (Measurement::VValve, v_valve_pin),
-        (Measurement::VPower, v_power_pin),
-        (Measurement::IPower, i_power_pin),
-        (Measurement::DiffSensors, diff_pin),
-        ]);
+    #[test]
+    fn sign_extend_reads_24_bit_twos_complement() {
+        assert_eq!(sign_extend(&[0x7F, 0xFF, 0xFF]), 8_388_607);
+        assert_eq!(sign_extend(&[0x80, 0x00, 0x00]), -8_388_608);
+        assert_eq!(sign_extend(&[0xFF, 0xFF, 0xFF]), -1);
+        assert_eq!(sign_extend(&[0x00, 0x00, 0x01]), 1);
+    }
 
-    gpio_mapping
-}
+    #[test]
+    fn sign_extend_reads_16_bit_twos_complement() {
+        assert_eq!(sign_extend(&[0x7F, 0xFF]), 32_767);
+        assert_eq!(sign_extend(&[0x80, 0x00]), -32_768);
+        assert_eq!(sign_extend(&[0xFF, 0xFF]), -1);
+    }
 
-pub fn pull_gpios_high(controllers: &Vec<Arc<Gpio>>) {
-    let pins = vec![controllers[0].get_pin(30), 
-                    controllers[2].get_pin(4),
-                    controllers[0].get_pin(26),
-                    controllers[2].get_pin(13),
-                    controllers[2].get_pin(15),
-                    controllers[0].get_pin(10),
-                    controllers[0].get_pin(20),
-                    controllers[3].get_pin(16),
-                    controllers[2].get_pin(11),
-                    controllers[0].get_pin(5),
-                    controllers[0].get_pin(13),
-                    controllers[0].get_pin(23),
-                    controllers[2].get_pin(23)];
-
-    for pin in pins.iter() {
-        pin.mode(Output);
-        pin.digital_write(High);
+    #[test]
+    fn full_scale_is_the_largest_code_magnitude() {
+        assert_eq!(registers::Part::Ads114s0x.full_scale(), 32_768.0);
+        assert_eq!(registers::Part::Ads124s0x.full_scale(), 8_388_608.0);
     }
 }
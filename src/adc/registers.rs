@@ -0,0 +1,799 @@
+//! Register map of the ADS114S0x and ADS124S0x.
+//!
+//! https://www.ti.com/lit/ds/symlink/ads114s08.pdf
+//! https://www.ti.com/lit/ds/symlink/ads124s08.pdf
+//! Section 9.6 describes every configuration register bit by bit. The two
+//! parts share everything up to OFCAL0, after which the 24-bit part's
+//! three-byte calibration registers push the rest up by two. Each
+//! register here is a small builder which starts from the register's reset
+//! value, so only the fields that differ from reset need to be set, e.g.
+//! `Pga::new().gain(Gain::X32)` encodes to 0x0D.
+
+use serde::{Deserialize, Serialize};
+
+pub const ID: u8 = 0x00;
+pub const STATUS: u8 = 0x01;
+pub const INPMUX: u8 = 0x02;
+pub const PGA: u8 = 0x03;
+pub const DATARATE: u8 = 0x04;
+pub const REF: u8 = 0x05;
+pub const IDACMAG: u8 = 0x06;
+pub const IDACMUX: u8 = 0x07;
+pub const VBIAS: u8 = 0x08;
+pub const SYS: u8 = 0x09;
+pub const OFCAL0: u8 = 0x0A;
+
+// the ADS114S0x's, see Part for the ADS124S0x
+pub const OFCAL1: u8 = 0x0B;
+pub const FSCAL0: u8 = 0x0C;
+pub const FSCAL1: u8 = 0x0D;
+pub const GPIODAT: u8 = 0x0E;
+pub const GPIOCON: u8 = 0x0F;
+
+// registers of the larger map, the ADS124S0x's
+pub const MAX_REGISTERS: usize = 18;
+
+/// Which member of the family is fitted. The ADS124S0x is pin compatible
+/// with the ADS114S0x, but converts to 24 bits rather than 16.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Part {
+    #[default]
+    Ads114s0x,
+    Ads124s0x,
+}
+
+impl Part {
+    pub const fn resolution(self) -> u32 {
+        match self {
+            Part::Ads114s0x => 16,
+            Part::Ads124s0x => 24,
+        }
+    }
+
+    /// Bytes of conversion data, and of each of OFCAL and FSCAL.
+    pub const fn word_bytes(self) -> usize {
+        self.resolution() as usize / 8
+    }
+
+    pub const fn num_registers(self) -> usize {
+        match self {
+            Part::Ads114s0x => 16,
+            Part::Ads124s0x => MAX_REGISTERS,
+        }
+    }
+
+    /// Code of a positive full-scale input, one more than the largest code.
+    pub fn full_scale(self) -> f64 {
+        (1u32 << (self.resolution() - 1)) as f64
+    }
+
+    /// Whether the DEV_ID field of ID names one of this part's variants.
+    pub fn matches_id(self, id: u8) -> bool {
+        match self {
+            Part::Ads114s0x => matches!(id & 0x07, 0x04 | 0x05),
+            Part::Ads124s0x => matches!(id & 0x07, 0x00 | 0x01),
+        }
+    }
+}
+
+/// STATUS, as read back or sent ahead of conversion data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u8);
+
+impl Status {
+    /// Set on power-up or reset until cleared by writing STATUS.
+    pub fn power_on_reset(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// The ADC is still starting up and can't be talked to yet.
+    pub fn not_ready(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    /// PGA output rail flags, only valid with rail detection enabled.
+    pub fn rails(&self) -> u8 {
+        (self.0 >> 2) & 0x0F
+    }
+
+    /// The reference is below the threshold set in REF.
+    pub fn reference_low(&self) -> bool {
+        self.0 & 0x03 != 0
+    }
+}
+
+/// A configuration register value that can be written with WREG.
+pub trait Register: Copy {
+    const ADDRESS: u8;
+
+    fn encode(self) -> u8;
+}
+
+/// Analog inputs selectable by the input, IDAC and bias multiplexers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u8")]
+pub enum Input {
+    Ain0,
+    Ain1,
+    Ain2,
+    Ain3,
+    Ain4,
+    Ain5,
+    Ain6,
+    Ain7,
+    Ain8,
+    Ain9,
+    Ain10,
+    Ain11,
+    AinCom,
+}
+
+impl Input {
+    const fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl TryFrom<u8> for Input {
+    type Error = String;
+
+    /// AINx by number, with AINCOM as 12.
+    fn try_from(number: u8) -> Result<Input, String> {
+        const INPUTS: [Input; 13] = [
+            Input::Ain0, Input::Ain1, Input::Ain2, Input::Ain3, Input::Ain4, Input::Ain5, Input::Ain6,
+            Input::Ain7, Input::Ain8, Input::Ain9, Input::Ain10, Input::Ain11, Input::AinCom,
+        ];
+
+        INPUTS
+            .get(number as usize)
+            .copied()
+            .ok_or_else(|| format!("{number} is not an analog input, expected 0-11 or 12 for AINCOM"))
+    }
+}
+
+/// INPMUX: which inputs are connected to the positive and negative side of
+/// the PGA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InpMux {
+    pub positive: Input,
+    pub negative: Input,
+}
+
+impl InpMux {
+    pub const fn new(positive: Input, negative: Input) -> InpMux {
+        InpMux { positive, negative }
+    }
+}
+
+impl Register for InpMux {
+    const ADDRESS: u8 = INPMUX;
+
+    fn encode(self) -> u8 {
+        (self.positive.code() << 4) | self.negative.code()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u32")]
+pub enum Gain {
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128,
+}
+
+impl Gain {
+    pub const fn factor(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl TryFrom<u32> for Gain {
+    type Error = String;
+
+    fn try_from(factor: u32) -> Result<Gain, String> {
+        match factor {
+            1 => Ok(Gain::X1),
+            2 => Ok(Gain::X2),
+            4 => Ok(Gain::X4),
+            8 => Ok(Gain::X8),
+            16 => Ok(Gain::X16),
+            32 => Ok(Gain::X32),
+            64 => Ok(Gain::X64),
+            128 => Ok(Gain::X128),
+            _ => Err(format!("{factor} is not a PGA gain, expected a power of 2 from 1 to 128")),
+        }
+    }
+}
+
+/// Delay between the start of a conversion and the first sample, in
+/// modulator clock periods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionDelay {
+    Tmod14,
+    Tmod25,
+    Tmod64,
+    Tmod256,
+    Tmod1024,
+    Tmod2048,
+    Tmod4096,
+    Tmod1,
+}
+
+/// PGA: conversion delay and gain. The PGA is bypassed until a gain is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pga {
+    delay: ConversionDelay,
+    gain: Option<Gain>,
+}
+
+impl Pga {
+    pub const fn new() -> Pga {
+        Pga { delay: ConversionDelay::Tmod14, gain: None }
+    }
+
+    pub const fn delay(mut self, delay: ConversionDelay) -> Pga {
+        self.delay = delay;
+        self
+    }
+
+    /// Enables the PGA at `gain`.
+    pub const fn gain(mut self, gain: Gain) -> Pga {
+        self.gain = Some(gain);
+        self
+    }
+
+    /// Effective gain of the signal path, 1 when the PGA is bypassed.
+    pub const fn factor(self) -> u32 {
+        match self.gain {
+            Some(gain) => gain.factor(),
+            None => 1,
+        }
+    }
+}
+
+impl Default for Pga {
+    fn default() -> Pga {
+        Pga::new()
+    }
+}
+
+impl Register for Pga {
+    const ADDRESS: u8 = PGA;
+
+    fn encode(self) -> u8 {
+        let (enable, gain) = match self.gain {
+            Some(gain) => (0x01, gain as u8),
+            None => (0x00, 0x00),
+        };
+
+        ((self.delay as u8) << 5) | (enable << 3) | gain
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "f64")]
+pub enum SampleRate {
+    Sps2_5,
+    Sps5,
+    Sps10,
+    Sps16_6,
+    Sps20,
+    Sps50,
+    Sps60,
+    Sps100,
+    Sps200,
+    Sps400,
+    Sps800,
+    Sps1000,
+    Sps2000,
+    Sps4000,
+}
+
+impl SampleRate {
+    const fn code(self) -> u8 {
+        match self {
+            // 1101 and 1110 both select 4000 SPS, SAM has always written 1110
+            SampleRate::Sps4000 => 0x0E,
+            rate => rate as u8,
+        }
+    }
+
+    pub fn samples_per_second(self) -> f64 {
+        match self {
+            SampleRate::Sps2_5 => 2.5,
+            SampleRate::Sps5 => 5.0,
+            SampleRate::Sps10 => 10.0,
+            SampleRate::Sps16_6 => 16.6,
+            SampleRate::Sps20 => 20.0,
+            SampleRate::Sps50 => 50.0,
+            SampleRate::Sps60 => 60.0,
+            SampleRate::Sps100 => 100.0,
+            SampleRate::Sps200 => 200.0,
+            SampleRate::Sps400 => 400.0,
+            SampleRate::Sps800 => 800.0,
+            SampleRate::Sps1000 => 1000.0,
+            SampleRate::Sps2000 => 2000.0,
+            SampleRate::Sps4000 => 4000.0,
+        }
+    }
+}
+
+impl TryFrom<f64> for SampleRate {
+    type Error = String;
+
+    fn try_from(samples_per_second: f64) -> Result<SampleRate, String> {
+        const RATES: [SampleRate; 14] = [
+            SampleRate::Sps2_5, SampleRate::Sps5, SampleRate::Sps10, SampleRate::Sps16_6,
+            SampleRate::Sps20, SampleRate::Sps50, SampleRate::Sps60, SampleRate::Sps100,
+            SampleRate::Sps200, SampleRate::Sps400, SampleRate::Sps800, SampleRate::Sps1000,
+            SampleRate::Sps2000, SampleRate::Sps4000,
+        ];
+
+        RATES
+            .into_iter()
+            .find(|rate| rate.samples_per_second() == samples_per_second)
+            .ok_or_else(|| format!("{samples_per_second} SPS is not a supported data rate"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Sinc3,
+    LowLatency,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionMode {
+    Continuous,
+    SingleShot,
+}
+
+/// DATARATE: global chop, clock source, conversion mode, filter and rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataRate {
+    global_chop: bool,
+    external_clock: bool,
+    mode: ConversionMode,
+    filter: Filter,
+    rate: SampleRate,
+}
+
+impl DataRate {
+    pub const fn new() -> DataRate {
+        DataRate {
+            global_chop: false,
+            external_clock: false,
+            mode: ConversionMode::Continuous,
+            filter: Filter::LowLatency,
+            rate: SampleRate::Sps20,
+        }
+    }
+
+    pub const fn global_chop(mut self, enabled: bool) -> DataRate {
+        self.global_chop = enabled;
+        self
+    }
+
+    pub const fn external_clock(mut self, enabled: bool) -> DataRate {
+        self.external_clock = enabled;
+        self
+    }
+
+    pub const fn mode(mut self, mode: ConversionMode) -> DataRate {
+        self.mode = mode;
+        self
+    }
+
+    pub const fn filter(mut self, filter: Filter) -> DataRate {
+        self.filter = filter;
+        self
+    }
+
+    pub const fn rate(mut self, rate: SampleRate) -> DataRate {
+        self.rate = rate;
+        self
+    }
+}
+
+impl Default for DataRate {
+    fn default() -> DataRate {
+        DataRate::new()
+    }
+}
+
+impl Register for DataRate {
+    const ADDRESS: u8 = DATARATE;
+
+    fn encode(self) -> u8 {
+        ((self.global_chop as u8) << 7)
+            | ((self.external_clock as u8) << 6)
+            | ((self.mode as u8) << 5)
+            | ((self.filter as u8) << 4)
+            | self.rate.code()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceSource {
+    /// REFP0/REFN0
+    Ref0,
+    /// REFP1/REFN1
+    Ref1,
+    /// The internal 2.5 V reference
+    Internal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InternalReference {
+    Off,
+    /// On, but powered down along with the device
+    OnUnlessPowerDown,
+    AlwaysOn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceMonitor {
+    Disabled,
+    /// Flags a reference below 0.3 V
+    Threshold,
+    /// Flags a reference below 0.3 V, with 10 MΩ pulling REFP and REFN together
+    ThresholdAndPullTogether,
+    /// Flags a reference below 0.3 V or below 1/3 of AVDD
+    LowReference,
+}
+
+/// REF: reference monitor, buffers, source and internal reference control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ref {
+    monitor: ReferenceMonitor,
+    positive_buffer: bool,
+    negative_buffer: bool,
+    source: ReferenceSource,
+    internal: InternalReference,
+}
+
+impl Ref {
+    pub const fn new() -> Ref {
+        Ref {
+            monitor: ReferenceMonitor::Disabled,
+            positive_buffer: true,
+            negative_buffer: false,
+            source: ReferenceSource::Ref0,
+            internal: InternalReference::Off,
+        }
+    }
+
+    pub const fn monitor(mut self, monitor: ReferenceMonitor) -> Ref {
+        self.monitor = monitor;
+        self
+    }
+
+    pub const fn positive_buffer(mut self, enabled: bool) -> Ref {
+        self.positive_buffer = enabled;
+        self
+    }
+
+    pub const fn negative_buffer(mut self, enabled: bool) -> Ref {
+        self.negative_buffer = enabled;
+        self
+    }
+
+    pub const fn source(mut self, source: ReferenceSource) -> Ref {
+        self.source = source;
+        self
+    }
+
+    pub const fn internal(mut self, internal: InternalReference) -> Ref {
+        self.internal = internal;
+        self
+    }
+}
+
+impl Default for Ref {
+    fn default() -> Ref {
+        Ref::new()
+    }
+}
+
+impl Register for Ref {
+    const ADDRESS: u8 = REF;
+
+    // the buffer bits are bypass bits, so an enabled buffer is a 0
+    fn encode(self) -> u8 {
+        ((self.monitor as u8) << 6)
+            | ((!self.positive_buffer as u8) << 5)
+            | ((!self.negative_buffer as u8) << 4)
+            | ((self.source as u8) << 2)
+            | self.internal as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "f64")]
+pub enum IdacMagnitude {
+    Off,
+    Ua10,
+    Ua50,
+    Ua100,
+    Ua250,
+    Ua500,
+    Ua750,
+    Ua1000,
+    Ua1500,
+    Ua2000,
+}
+
+impl IdacMagnitude {
+    pub fn microamps(self) -> f64 {
+        match self {
+            IdacMagnitude::Off => 0.0,
+            IdacMagnitude::Ua10 => 10.0,
+            IdacMagnitude::Ua50 => 50.0,
+            IdacMagnitude::Ua100 => 100.0,
+            IdacMagnitude::Ua250 => 250.0,
+            IdacMagnitude::Ua500 => 500.0,
+            IdacMagnitude::Ua750 => 750.0,
+            IdacMagnitude::Ua1000 => 1000.0,
+            IdacMagnitude::Ua1500 => 1500.0,
+            IdacMagnitude::Ua2000 => 2000.0,
+        }
+    }
+}
+
+impl TryFrom<f64> for IdacMagnitude {
+    type Error = String;
+
+    fn try_from(microamps: f64) -> Result<IdacMagnitude, String> {
+        const MAGNITUDES: [IdacMagnitude; 10] = [
+            IdacMagnitude::Off, IdacMagnitude::Ua10, IdacMagnitude::Ua50, IdacMagnitude::Ua100,
+            IdacMagnitude::Ua250, IdacMagnitude::Ua500, IdacMagnitude::Ua750, IdacMagnitude::Ua1000,
+            IdacMagnitude::Ua1500, IdacMagnitude::Ua2000,
+        ];
+
+        MAGNITUDES
+            .into_iter()
+            .find(|magnitude| magnitude.microamps() == microamps)
+            .ok_or_else(|| format!("{microamps} uA is not a supported excitation current"))
+    }
+}
+
+/// IDACMAG: excitation current magnitude, low-side switch and PGA rail
+/// detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdacMag {
+    rail_detection: bool,
+    low_side_switch: bool,
+    magnitude: IdacMagnitude,
+}
+
+impl IdacMag {
+    pub const fn new() -> IdacMag {
+        IdacMag { rail_detection: false, low_side_switch: false, magnitude: IdacMagnitude::Off }
+    }
+
+    pub const fn rail_detection(mut self, enabled: bool) -> IdacMag {
+        self.rail_detection = enabled;
+        self
+    }
+
+    pub const fn low_side_switch(mut self, closed: bool) -> IdacMag {
+        self.low_side_switch = closed;
+        self
+    }
+
+    pub const fn magnitude(mut self, magnitude: IdacMagnitude) -> IdacMag {
+        self.magnitude = magnitude;
+        self
+    }
+}
+
+impl Default for IdacMag {
+    fn default() -> IdacMag {
+        IdacMag::new()
+    }
+}
+
+impl Register for IdacMag {
+    const ADDRESS: u8 = IDACMAG;
+
+    fn encode(self) -> u8 {
+        ((self.rail_detection as u8) << 7) | ((self.low_side_switch as u8) << 6) | self.magnitude as u8
+    }
+}
+
+/// IDACMUX: which input each excitation current source drives. Both are
+/// disconnected until routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdacMux {
+    idac1: Option<Input>,
+    idac2: Option<Input>,
+}
+
+impl IdacMux {
+    pub const fn new() -> IdacMux {
+        IdacMux { idac1: None, idac2: None }
+    }
+
+    pub const fn idac1(mut self, input: Input) -> IdacMux {
+        self.idac1 = Some(input);
+        self
+    }
+
+    pub const fn idac2(mut self, input: Input) -> IdacMux {
+        self.idac2 = Some(input);
+        self
+    }
+}
+
+impl Default for IdacMux {
+    fn default() -> IdacMux {
+        IdacMux::new()
+    }
+}
+
+impl Register for IdacMux {
+    const ADDRESS: u8 = IDACMUX;
+
+    fn encode(self) -> u8 {
+        let code = |input: Option<Input>| input.map_or(0x0F, Input::code);
+
+        (code(self.idac2) << 4) | code(self.idac1)
+    }
+}
+
+/// Inputs the bias voltage can be applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiasInput {
+    Ain0,
+    Ain1,
+    Ain2,
+    Ain3,
+    Ain4,
+    Ain5,
+    AinCom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiasLevel {
+    /// (AVDD + AVSS) / 2
+    Half,
+    /// (AVDD + AVSS) / 12
+    Twelfth,
+}
+
+/// VBIAS: bias voltage level and the inputs it is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vbias {
+    level: BiasLevel,
+    inputs: u8,
+}
+
+impl Vbias {
+    pub const fn new() -> Vbias {
+        Vbias { level: BiasLevel::Half, inputs: 0 }
+    }
+
+    pub const fn level(mut self, level: BiasLevel) -> Vbias {
+        self.level = level;
+        self
+    }
+
+    pub const fn bias(mut self, input: BiasInput) -> Vbias {
+        self.inputs |= 1 << input as u8;
+        self
+    }
+}
+
+impl Default for Vbias {
+    fn default() -> Vbias {
+        Vbias::new()
+    }
+}
+
+impl Register for Vbias {
+    const ADDRESS: u8 = VBIAS;
+
+    fn encode(self) -> u8 {
+        ((self.level as u8) << 7) | self.inputs
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemMonitor {
+    Disabled,
+    /// PGA inputs shorted to mid-supply
+    Short,
+    /// Internal temperature sensor
+    Temperature,
+    /// (AVDD - AVSS) / 4
+    Avdd,
+    /// DVDD / 4
+    Dvdd,
+    /// 0.2 µA burnout current sources
+    Burnout0_2,
+    /// 1 µA burnout current sources
+    Burnout1,
+    /// 10 µA burnout current sources
+    Burnout10,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationSamples {
+    One,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+/// SYS: system monitor, calibration averaging, SPI timeout, CRC and STATUS
+/// byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sys {
+    monitor: SystemMonitor,
+    calibration_samples: CalibrationSamples,
+    timeout: bool,
+    crc: bool,
+    status: bool,
+}
+
+impl Sys {
+    pub const fn new() -> Sys {
+        Sys {
+            monitor: SystemMonitor::Disabled,
+            calibration_samples: CalibrationSamples::Eight,
+            timeout: false,
+            crc: false,
+            status: false,
+        }
+    }
+
+    pub const fn monitor(mut self, monitor: SystemMonitor) -> Sys {
+        self.monitor = monitor;
+        self
+    }
+
+    pub const fn calibration_samples(mut self, samples: CalibrationSamples) -> Sys {
+        self.calibration_samples = samples;
+        self
+    }
+
+    pub const fn timeout(mut self, enabled: bool) -> Sys {
+        self.timeout = enabled;
+        self
+    }
+
+    pub const fn crc(mut self, enabled: bool) -> Sys {
+        self.crc = enabled;
+        self
+    }
+
+    pub const fn status(mut self, enabled: bool) -> Sys {
+        self.status = enabled;
+        self
+    }
+}
+
+impl Default for Sys {
+    fn default() -> Sys {
+        Sys::new()
+    }
+}
+
+impl Register for Sys {
+    const ADDRESS: u8 = SYS;
+
+    fn encode(self) -> u8 {
+        ((self.monitor as u8) << 5)
+            | ((self.calibration_samples as u8) << 3)
+            | ((self.timeout as u8) << 2)
+            | ((self.crc as u8) << 1)
+            | self.status as u8
+    }
+}
@@ -0,0 +1,1048 @@
+//! Software model of a TI ADS114S0x, or its 24-bit sibling the ADS124S0x,
+//! sitting on the SPI bus.
+//!
+//! https://www.ti.com/lit/ds/symlink/ads114s08.pdf
+//! https://www.ti.com/lit/ds/symlink/ads124s08.pdf
+//! The model decodes the same command set the driver in adc.rs sends (RESET,
+//! START, STOP, RDATA, RREG, WREG and the calibration commands), keeps a full
+//! register file, and produces conversion results from programmable analog
+//! inputs using the INPMUX, PGA, REF and SYS settings currently written to it.
+//! Codes can also be queued directly to script exact RDATA responses. RDATA
+//! is framed by the STATUS byte and CRC when SENDSTAT and CRC are set in SYS.
+//! Clocking with DIN low reads the data directly, and commands may follow the
+//! data (or each other) in the same transfer.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+
+use crate::spi::SpiTransport;
+
+const ID: usize = 0x00;
+const STATUS: usize = 0x01;
+const INPMUX: usize = 0x02;
+const PGA: usize = 0x03;
+const DATARATE: usize = 0x04;
+const REF: usize = 0x05;
+const SYS: usize = 0x09;
+const OFCAL0: usize = 0x0A; // FSCAL0 follows the last OFCAL byte
+
+const RESET_REGISTERS: [u8; 16] = [
+    0x05, // ID (ADS114S06)
+    0x80, // STATUS
+    0x01, // INPMUX
+    0x00, // PGA
+    0x14, // DATARATE
+    0x10, // REF
+    0x00, // IDACMAG
+    0xFF, // IDACMUX
+    0x00, // VBIAS
+    0x10, // SYS
+    0x00, // OFCAL0
+    0x00, // OFCAL1
+    0x00, // FSCAL0
+    0x40, // FSCAL1
+    0x00, // GPIODAT
+    0x00, // GPIOCON
+];
+
+const RESET_REGISTERS_24: [u8; 18] = [
+    0x01, // ID (ADS124S06)
+    0x80, // STATUS
+    0x01, // INPMUX
+    0x00, // PGA
+    0x14, // DATARATE
+    0x10, // REF
+    0x00, // IDACMAG
+    0xFF, // IDACMUX
+    0x00, // VBIAS
+    0x10, // SYS
+    0x00, // OFCAL0
+    0x00, // OFCAL1
+    0x00, // OFCAL2
+    0x00, // FSCAL0
+    0x00, // FSCAL1
+    0x40, // FSCAL2
+    0x00, // GPIODAT
+    0x00, // GPIOCON
+];
+
+const INTERNAL_REFERENCE: f64 = 2.5;
+const NUM_INPUTS: usize = 13; // AIN0-AIN11 and AINCOM
+
+struct Device {
+    reset_registers: &'static [u8],
+    registers: Vec<u8>,
+    // bytes of conversion data, and of each calibration register
+    word: usize,
+    inputs: [f64; NUM_INPUTS],
+    disconnected: [bool; NUM_INPUTS],
+    external_reference: f64,
+    die_temperature: f64,
+    avdd: f64,
+    dvdd: f64,
+    offset: i32,
+    queued_codes: VecDeque<i32>,
+    // registers stuck at a value, which ignore writes
+    held: Vec<Option<u8>>,
+    last_code: i32,
+    converting: bool,
+    commands: Vec<u8>,
+}
+
+/// Scriptable stand-in for an ADS114S0x. Implements `SpiTransport`, so it can
+/// be handed to an `ADC` in place of a `Spidev`.
+pub struct FakeAds114s0x {
+    device: RefCell<Device>,
+}
+
+impl FakeAds114s0x {
+    pub fn new() -> FakeAds114s0x {
+        FakeAds114s0x::with_registers(&RESET_REGISTERS, 2)
+    }
+
+    /// A 24-bit ADS124S0x.
+    pub fn ads124s0x() -> FakeAds114s0x {
+        FakeAds114s0x::with_registers(&RESET_REGISTERS_24, 3)
+    }
+
+    fn with_registers(reset_registers: &'static [u8], word: usize) -> FakeAds114s0x {
+        let device = Device {
+            reset_registers,
+            registers: reset_registers.to_vec(),
+            word,
+            inputs: [0.0; NUM_INPUTS],
+            disconnected: [false; NUM_INPUTS],
+            external_reference: INTERNAL_REFERENCE,
+            die_temperature: 25.0,
+            avdd: 5.0,
+            dvdd: 3.3,
+            offset: 0,
+            queued_codes: VecDeque::new(),
+            held: vec![None; reset_registers.len()],
+            last_code: 0,
+            converting: false,
+            commands: Vec::new(),
+        };
+
+        FakeAds114s0x { device: RefCell::new(device) }
+    }
+
+    /// Sets the voltage on AINx (0-11) or AINCOM (12), relative to AVSS.
+    pub fn set_input(&self, input: usize, volts: f64) {
+        self.device.borrow_mut().inputs[input] = volts;
+    }
+
+    /// Disconnects an input from its sensor, so the burnout current sources
+    /// pull it to the rail.
+    pub fn set_disconnected(&self, input: usize, disconnected: bool) {
+        self.device.borrow_mut().disconnected[input] = disconnected;
+    }
+
+    /// Sets the voltage across REFP0/REFN0, used whenever REFSEL selects it.
+    pub fn set_external_reference(&self, volts: f64) {
+        self.device.borrow_mut().external_reference = volts;
+    }
+
+    pub fn set_die_temperature(&self, celsius: f64) {
+        self.device.borrow_mut().die_temperature = celsius;
+    }
+
+    pub fn set_supplies(&self, avdd: f64, dvdd: f64) {
+        let mut device = self.device.borrow_mut();
+        device.avdd = avdd;
+        device.dvdd = dvdd;
+    }
+
+    /// Sets the raw offset error the converter adds before calibration.
+    pub fn set_offset(&self, code: i32) {
+        self.device.borrow_mut().offset = code;
+    }
+
+    /// Queues a code to be returned by the next conversion, bypassing the
+    /// analog model.
+    pub fn queue_code(&self, code: i32) {
+        self.device.borrow_mut().queued_codes.push_back(code);
+    }
+
+    pub fn register(&self, address: u8) -> u8 {
+        self.device.borrow().registers[address as usize]
+    }
+
+    pub fn registers(&self) -> Vec<u8> {
+        self.device.borrow().registers.clone()
+    }
+
+    /// Overwrites a register behind the driver's back, as a glitch would.
+    pub fn set_register(&self, address: u8, value: u8) {
+        self.device.borrow_mut().registers[address as usize] = value;
+    }
+
+    /// Sticks a register at `value`, or frees it with `None`. A stuck
+    /// register reads back `value` whatever is written to it.
+    pub fn hold_register(&self, address: u8, value: Option<u8>) {
+        let mut device = self.device.borrow_mut();
+        device.held[address as usize] = value;
+        device.apply_held();
+    }
+
+    /// A rising edge on START/SYNC, which starts converting like the START
+    /// command.
+    pub fn pulse_start(&self) {
+        self.device.borrow_mut().converting = true;
+    }
+
+    pub fn is_converting(&self) -> bool {
+        self.device.borrow().converting
+    }
+
+    /// Every command opcode received so far, in order.
+    pub fn commands(&self) -> Vec<u8> {
+        self.device.borrow().commands.clone()
+    }
+}
+
+impl Default for FakeAds114s0x {
+    fn default() -> FakeAds114s0x {
+        FakeAds114s0x::new()
+    }
+}
+
+impl Device {
+    fn reset(&mut self) {
+        self.registers = self.reset_registers.to_vec();
+        self.apply_held();
+        self.last_code = 0;
+        self.converting = false;
+    }
+
+    fn apply_held(&mut self) {
+        for (register, held) in self.registers.iter_mut().zip(&self.held) {
+            if let Some(value) = held {
+                *register = *value;
+            }
+        }
+    }
+
+    fn gain(&self) -> f64 {
+        let pga = self.registers[PGA];
+
+        // PGA_EN = 01 enables the PGA, otherwise it is bypassed at gain 1
+        if (pga >> 3) & 0x03 == 0x01 {
+            (1 << (pga & 0x07)) as f64
+        } else {
+            1.0
+        }
+    }
+
+    fn reference(&self) -> f64 {
+        match (self.registers[REF] >> 2) & 0x03 {
+            0x02 => INTERNAL_REFERENCE,
+            _ => self.external_reference,
+        }
+    }
+
+    fn differential_input(&self) -> f64 {
+        match self.registers[SYS] >> 5 {
+            0x01 => 0.0,
+            0x02 => 0.129 + (self.die_temperature - 25.0) * 0.000403,
+            0x03 => self.avdd / 4.0,
+            0x04 => self.dvdd / 4.0,
+            monitor => {
+                let muxp = (self.registers[INPMUX] >> 4) as usize;
+                let muxn = (self.registers[INPMUX] & 0x0F) as usize;
+
+                // the burnout sources push AINP up and pull AINN down
+                let disconnected = |input: usize| self.disconnected.get(input).copied().unwrap_or(false);
+                if monitor >= 0x05 && (disconnected(muxp) || disconnected(muxn)) {
+                    return self.avdd;
+                }
+
+                let positive = self.inputs.get(muxp).copied().unwrap_or(0.0);
+                let negative = self.inputs.get(muxn).copied().unwrap_or(0.0);
+
+                positive - negative
+            }
+        }
+    }
+
+    // positive full-scale code, one more than the largest
+    fn full_scale(&self) -> i64 {
+        1 << (8 * self.word - 1)
+    }
+
+    fn clamp(&self, code: i64) -> i32 {
+        code.clamp(-self.full_scale(), self.full_scale() - 1) as i32
+    }
+
+    // a little-endian calibration register, OFCAL0 or FSCAL0 onwards
+    fn calibration_register(&self, address: usize) -> i64 {
+        self.registers[address..address + self.word]
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as i64)
+    }
+
+    fn set_calibration_register(&mut self, address: usize, value: i64) {
+        for (i, byte) in value.to_le_bytes().iter().take(self.word).enumerate() {
+            self.registers[address + i] = *byte;
+        }
+    }
+
+    fn ofcal(&self) -> i64 {
+        let ofcal = self.calibration_register(OFCAL0);
+
+        // sign extend from the top bit of the register
+        if ofcal >= self.full_scale() { ofcal - 2 * self.full_scale() } else { ofcal }
+    }
+
+    fn fscal(&self) -> i64 {
+        self.calibration_register(OFCAL0 + self.word)
+    }
+
+    // FSCAL at unity gain
+    fn unity(&self) -> i64 {
+        self.full_scale() / 2
+    }
+
+    fn uncalibrated_code(&self) -> i64 {
+        let volts = self.differential_input() * self.gain();
+        let code = (volts / self.reference() * self.full_scale() as f64).round() as i64;
+
+        code + self.offset as i64
+    }
+
+    fn calibrate(&self, code: i64) -> i32 {
+        self.clamp((code - self.ofcal()) * self.fscal() / self.unity())
+    }
+
+    fn convert(&mut self) -> i32 {
+        if let Some(code) = self.queued_codes.pop_front() {
+            self.last_code = code;
+        } else if self.converting {
+            self.last_code = self.calibrate(self.uncalibrated_code());
+
+            // MODE = 1 is single-shot, which stops after one conversion
+            if self.registers[DATARATE] & 0x20 != 0 {
+                self.converting = false;
+            }
+        }
+
+        // RDY clears once there is data to read
+        self.registers[STATUS] &= !0x40;
+        self.last_code
+    }
+
+    // the next conversion as it is shifted out, framed by STATUS and CRC when
+    // SYS asks for them
+    fn data_frame(&mut self) -> Vec<u8> {
+        let code = self.convert().to_be_bytes();
+        let mut frame = Vec::with_capacity(self.word + 2);
+
+        if self.registers[SYS] & 0x01 != 0 {
+            frame.push(self.registers[STATUS]);
+        }
+
+        frame.extend_from_slice(&code[4 - self.word..]);
+
+        if self.registers[SYS] & 0x02 != 0 {
+            frame.push(crc8(&frame));
+        }
+
+        frame
+    }
+
+    fn self_offset_calibrate(&mut self) {
+        let code = self.clamp(self.offset as i64);
+        self.set_calibration_register(OFCAL0, code as i64);
+    }
+
+    fn system_offset_calibrate(&mut self) {
+        let code = self.clamp(self.uncalibrated_code());
+        self.set_calibration_register(OFCAL0, code as i64);
+    }
+
+    fn system_gain_calibrate(&mut self) {
+        let measured = self.uncalibrated_code() - self.ofcal();
+
+        if measured > 0 {
+            let fscal = (self.unity() * (self.full_scale() - 1) / measured).clamp(0, 2 * self.full_scale() - 1);
+            self.set_calibration_register(OFCAL0 + self.word, fscal);
+        }
+    }
+
+    fn read_registers(&self, start: usize, count: usize, rx: &mut [u8]) {
+        for (i, byte) in rx.iter_mut().skip(2).take(count).enumerate() {
+            *byte = self.registers.get(start + i).copied().unwrap_or(0);
+        }
+    }
+
+    fn write_registers(&mut self, start: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let address = start + i;
+
+            // ID is read-only
+            if address != ID && address < self.registers.len() && self.held[address].is_none() {
+                self.registers[address] = *byte;
+            }
+        }
+    }
+}
+
+// CRC-8-ATM seeded with 0xFF, over STATUS (when sent) and the data bytes
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0xFF, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 })
+    })
+}
+
+impl SpiTransport for FakeAds114s0x {
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+        if tx.len() != rx.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "tx and rx lengths differ"));
+        }
+
+        rx.fill(0);
+
+        let mut device = self.device.borrow_mut();
+        let mut position = 0;
+
+        // clocking with DIN low reads the conversion directly, and any
+        // commands after the data are decoded as usual
+        if tx.first() == Some(&0x00) {
+            let frame = device.data_frame();
+            let length = frame.len().min(rx.len());
+            rx[..length].copy_from_slice(&frame[..length]);
+            position = length;
+        }
+
+        while position < tx.len() {
+            let command = tx[position];
+            let tx = &tx[position..];
+            let rx = &mut rx[position..];
+
+            if command != 0x00 {
+                device.commands.push(command);
+            }
+
+            let count = tx.get(1).map_or(1, |n| (*n & 0x1F) as usize + 1);
+
+            position += match command {
+                0x06 | 0x07 => { device.reset(); 1 }
+                0x08 | 0x09 => { device.converting = true; 1 }
+                0x0A | 0x0B => { device.converting = false; 1 }
+                0x12 | 0x13 => {
+                    let frame = device.data_frame();
+
+                    for (byte, value) in rx.iter_mut().skip(1).zip(&frame) {
+                        *byte = *value;
+                    }
+
+                    1 + frame.len()
+                }
+                0x16 => { device.system_offset_calibrate(); 1 }
+                0x17 => { device.system_gain_calibrate(); 1 }
+                0x19 => { device.self_offset_calibrate(); 1 }
+                0x20..=0x3F => {
+                    device.read_registers((command & 0x1F) as usize, count, rx);
+                    2 + count
+                }
+                0x40..=0x5F => {
+                    let data = tx.get(2..).unwrap_or(&[]);
+                    device.write_registers((command & 0x1F) as usize, &data[..count.min(data.len())]);
+                    2 + count
+                }
+                _ => 1,
+            };
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adc::{registers::{Part, DATARATE, IDACMAG, IDACMUX, INPMUX, PGA, REF, STATUS, SYS}, Acquisition, ChannelConfig, Measurement, Settling, ADC};
+    use crate::gpio::Gpio;
+    use crate::{calibration::{CalibrationStore, Routine}, error::SamError, transducer::{PressureUnit, Transducer}, transfer::Transfer};
+    use std::{collections::HashMap, rc::Rc};
+
+    const SINGLE_ENDED: [Measurement; 5] = [
+        Measurement::CurrentLoopPt,
+        Measurement::VValve,
+        Measurement::IValve,
+        Measurement::VPower,
+        Measurement::IPower,
+    ];
+
+    fn adc(measurement: Measurement) -> (Rc<FakeAds114s0x>, ADC<FakeAds114s0x>) {
+        let fake = Rc::new(FakeAds114s0x::new());
+        let adc = ADC::new(measurement, fake.clone(), Rc::new(HashMap::new()), Rc::new(HashMap::new()));
+
+        (fake, adc)
+    }
+
+    // an ADC whose data ready pin always reads low, read with `acquisition`
+    fn with_data_ready(measurement: Measurement, acquisition: Acquisition) -> (Rc<FakeAds114s0x>, ADC<FakeAds114s0x>) {
+        let fake = Rc::new(FakeAds114s0x::new());
+        let drdy = HashMap::from([(measurement, Gpio::in_memory().get_pin(0))]);
+        let mut adc = ADC::new(measurement, fake.clone(), Rc::new(HashMap::new()), Rc::new(drdy));
+        adc.set_acquisition(acquisition);
+        adc.initialize().unwrap();
+
+        (fake, adc)
+    }
+
+    fn settling(measurement: Measurement, settling: &[Settling]) -> Vec<ChannelConfig> {
+        settling
+            .iter()
+            .map(|settling| ChannelConfig { settling: *settling, ..measurement.default_channel_config() })
+            .collect()
+    }
+
+    // conversions read out so far, wanted or not
+    fn rdata(fake: &FakeAds114s0x) -> usize {
+        fake.commands().iter().filter(|command| **command == 0x12).count()
+    }
+
+    // PGA, DATARATE, REF, IDACMAG, IDACMUX and SYS
+    fn configuration(fake: &FakeAds114s0x) -> [u8; 6] {
+        [PGA, DATARATE, REF, IDACMAG, IDACMUX, SYS].map(|address| fake.register(address))
+    }
+
+    fn initialized(measurement: Measurement) -> (Rc<FakeAds114s0x>, ADC<FakeAds114s0x>) {
+        let (fake, mut adc) = adc(measurement);
+        adc.initialize().unwrap();
+
+        (fake, adc)
+    }
+
+    // reads `code` back through RDATA as the channel at `iteration`
+    fn decode(adc: &mut ADC<FakeAds114s0x>, fake: &FakeAds114s0x, iteration: u64, code: i32) -> f64 {
+        adc.write_iteration(iteration);
+        fake.queue_code(code);
+        adc.test_read_individual(iteration).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn init_regs_configures_each_measurement() {
+        for measurement in SINGLE_ENDED {
+            let (fake, mut adc) = adc(measurement);
+            adc.init_regs().unwrap();
+
+            // PGA bypassed, 4000 SPS low-latency, internal reference, STATUS and CRC on
+            assert_eq!(configuration(&fake), [0x00, 0x1E, 0x4A, 0x00, 0xFF, 0x13], "{measurement:?}");
+            assert_eq!(fake.register(STATUS), 0x00);
+        }
+
+        for measurement in [Measurement::Tc1, Measurement::Tc2, Measurement::DiffSensors] {
+            let (fake, mut adc) = adc(measurement);
+            adc.init_regs().unwrap();
+
+            assert_eq!(configuration(&fake), [0x0D, 0x1E, 0x4A, 0x00, 0xFF, 0x13], "{measurement:?}");
+        }
+
+        // gain 2 against REF0, 1 mA out of AIN5 and AIN0
+        let (fake, mut adc) = adc(Measurement::Rtd);
+        adc.init_regs().unwrap();
+        assert_eq!(configuration(&fake), [0x09, 0x1E, 0x10, 0x07, 0x05, 0x13]);
+    }
+
+    #[test]
+    fn write_iteration_selects_each_channel() {
+        let inpmux: [(Measurement, &[u8]); 7] = [
+            (Measurement::CurrentLoopPt, &[0x0C, 0x1C, 0x2C, 0x3C, 0x4C, 0x5C]),
+            (Measurement::VValve, &[0x5C, 0x4C, 0x3C, 0x2C, 0x1C, 0x0C]),
+            (Measurement::IValve, &[0x5C, 0x4C, 0x3C, 0x2C, 0x1C, 0x0C]),
+            (Measurement::VPower, &[0x0C, 0x1C, 0x2C, 0x3C, 0x4C]),
+            (Measurement::IPower, &[0x0C, 0x1C]),
+            (Measurement::DiffSensors, &[0x54, 0x32, 0x10]),
+            (Measurement::Rtd, &[0x12, 0x34]),
+        ];
+
+        for (measurement, expected) in inpmux {
+            let (fake, mut adc) = initialized(measurement);
+
+            for (iteration, inpmux) in expected.iter().enumerate() {
+                adc.write_iteration(iteration as u64);
+                assert_eq!(fake.register(INPMUX), *inpmux, "{measurement:?} channel {iteration}");
+            }
+        }
+    }
+
+    #[test]
+    fn rtd_channels_switch_reference() {
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+
+        adc.write_iteration(0);
+        assert_eq!(fake.register(REF), 0x50);
+
+        adc.write_iteration(1);
+        assert_eq!(fake.register(REF), 0x54);
+    }
+
+    #[test]
+    fn thermocouple_scan_starts_at_the_cold_junction() {
+        for measurement in [Measurement::Tc1, Measurement::Tc2] {
+            let (fake, mut adc) = initialized(measurement);
+
+            // unity gain on the temperature sensor
+            adc.write_iteration(0);
+            assert_eq!([fake.register(PGA), fake.register(SYS)], [0x08, 0x43]);
+
+            for (iteration, inpmux) in [(1, 0x54), (2, 0x32), (3, 0x10)] {
+                adc.write_iteration(iteration);
+                assert_eq!([fake.register(PGA), fake.register(INPMUX), fake.register(SYS)], [0x0D, inpmux, 0x03]);
+            }
+        }
+    }
+
+    #[test]
+    fn single_ended_codes_decode_to_volts() {
+        // codes are offset by half the range against the 0 V negative input
+        let (fake, mut adc) = initialized(Measurement::IPower);
+        assert_close(decode(&mut adc, &fake, 0, -32768), 0.0);
+        assert_close(decode(&mut adc, &fake, 1, 0), 2.5);
+        assert_close(decode(&mut adc, &fake, 0, 16384), 3.75);
+
+        let (fake, mut adc) = initialized(Measurement::CurrentLoopPt);
+        assert_close(decode(&mut adc, &fake, 3, -16384), 1.25);
+
+        // behind the 11:1 divider
+        let (fake, mut adc) = initialized(Measurement::VValve);
+        assert_close(decode(&mut adc, &fake, 2, 0), 27.5);
+    }
+
+    #[test]
+    fn differential_codes_decode_at_channel_gain() {
+        let (fake, mut adc) = initialized(Measurement::DiffSensors);
+        assert_close(decode(&mut adc, &fake, 1, 16384), 2.5 / 2.0 / 32.0);
+        assert_close(decode(&mut adc, &fake, 2, -32768), -2.5 / 32.0);
+
+        // half the voltage across an RTD without a reference resistor
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+        assert_close(decode(&mut adc, &fake, 0, 16384), 2.5 / 2.0 / 2.0 / 2.0);
+    }
+
+    #[test]
+    fn thermocouples_decode_against_the_cold_junction() {
+        let (fake, mut adc) = initialized(Measurement::Tc1);
+
+        // 129 mV from the temperature sensor is 25 C
+        let code = (0.129 / 2.5 * 32768.0_f64).round() as i32;
+        let ambient = decode(&mut adc, &fake, 0, code);
+        assert!((ambient - 25.0).abs() < 0.05, "{ambient}");
+
+        // no thermoelectric voltage means the junction is at ambient
+        let kelvin = decode(&mut adc, &fake, 1, 0);
+        assert!((kelvin - (ambient + 273.15)).abs() < 0.1, "{kelvin}");
+
+        // about 1 mV is 25 C above ambient for a type K junction
+        let code = (0.001 * 32.0 / 2.5 * 32768.0_f64).round() as i32;
+        let kelvin = decode(&mut adc, &fake, 2, code);
+        assert!((kelvin - (ambient + 273.15 + 25.0)).abs() < 1.0, "{kelvin}");
+    }
+
+    #[test]
+    fn calibration_is_restored_from_the_store() {
+        let dir = std::env::temp_dir().join(format!("sam-calibration-{}-restore", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let lsb = 2.5 / 32768.0;
+
+        // an offset error of 40 codes
+        let (fake, mut adc) = initialized(Measurement::IPower);
+        fake.set_offset(40);
+        adc.write_iteration(0);
+        let uncalibrated = adc.test_read_individual(0).unwrap();
+
+        let coefficients = adc.calibrate(0, Routine::SelfOffset).unwrap();
+        assert_eq!(coefficients.offset, 40);
+
+        let calibrated = adc.test_read_individual(0).unwrap();
+        assert_close(uncalibrated - calibrated, 40.0 * lsb);
+
+        let mut store = CalibrationStore::open(&dir).unwrap();
+        store.save(Measurement::IPower, 0, adc.part(), Routine::SelfOffset, coefficients).unwrap();
+
+        // after a restart, the same ADC reads the same once the store is
+        // restored into it
+        let fake = Rc::new(FakeAds114s0x::new());
+        let mut restarted = ADC::new(Measurement::IPower, fake.clone(), Rc::new(HashMap::new()), Rc::new(HashMap::new()));
+        fake.set_offset(40);
+
+        for record in CalibrationStore::open(&dir).unwrap().records() {
+            restarted.set_calibration(record.channel, record.coefficients()).unwrap();
+        }
+
+        restarted.initialize().unwrap();
+        assert_eq!(fake.registers()[OFCAL0..OFCAL0 + 2], [40, 0]);
+        assert_close(restarted.test_read_individual(0).unwrap(), calibrated);
+
+        // other channels stay uncalibrated
+        restarted.write_iteration(1);
+        assert_close(restarted.test_read_individual(1).unwrap(), uncalibrated);
+        assert_eq!(fake.registers()[OFCAL0..OFCAL0 + 2], [0, 0]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_glitched_register_is_caught_and_rewritten() {
+        let (fake, mut adc) = initialized(Measurement::DiffSensors);
+        assert!(adc.verify_regs().is_ok());
+
+        fake.set_register(PGA, 0x08);
+
+        match adc.verify_regs() {
+            Err(SamError::AdcRegister { register, expected, actual, .. }) => {
+                assert_eq!((register, expected, actual), (PGA, 0x0D, 0x08));
+            }
+            other => panic!("expected a register mismatch, got {other:?}"),
+        }
+
+        adc.init_regs().unwrap();
+        assert!(!adc.is_faulted());
+        assert_eq!(fake.register(PGA), 0x0D);
+    }
+
+    #[test]
+    fn a_register_that_wont_take_faults_after_every_attempt() {
+        let (fake, mut adc) = adc(Measurement::IPower);
+        fake.hold_register(DATARATE, Some(0x14));
+
+        assert!(matches!(adc.init_regs(), Err(SamError::AdcRegister { register: DATARATE, actual: 0x14, .. })));
+        assert!(adc.is_faulted());
+
+        // one write of DATARATE per attempt
+        let writes = fake.commands().iter().filter(|command| **command == 0x40 | DATARATE).count();
+        assert_eq!(writes, 3);
+
+        // a later attempt that takes clears the fault
+        fake.hold_register(DATARATE, None);
+        adc.init_regs().unwrap();
+        assert!(!adc.is_faulted());
+    }
+
+    #[test]
+    fn the_wrong_part_is_rejected_by_id() {
+        let (_, mut adc) = adc(Measurement::IPower);
+        adc.set_part(Part::Ads124s0x);
+
+        assert!(matches!(adc.init_regs(), Err(SamError::AdcPart { id: 0x05, .. })));
+        assert!(adc.is_faulted());
+    }
+
+    #[test]
+    fn a_reset_adc_is_caught_and_reinitialized() {
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+
+        // a brownout puts every register back, STATUS and CRC included
+        fake.transfer(&[0x06], &mut [0x00]).unwrap();
+
+        assert!(matches!(adc.read_conversion(), Err(SamError::AdcReset { .. })));
+        assert!(matches!(adc.verify_regs(), Err(SamError::AdcRegister { .. })));
+
+        adc.initialize().unwrap();
+        adc.verify_regs().unwrap();
+        assert!(adc.read_conversion().is_ok());
+
+        // the power-on flag alone, with the configuration intact
+        fake.set_register(STATUS, 0x80);
+        assert!(matches!(adc.get_adc_reading(0), Err(SamError::AdcReset { .. })));
+        assert_eq!(adc.rejected().resets, 1);
+
+        adc.initialize().unwrap();
+        assert!(adc.read_conversion().is_ok());
+    }
+
+    #[test]
+    fn burnout_check_flags_only_open_thermocouples() {
+        let (fake, mut adc) = initialized(Measurement::Tc1);
+
+        // thermocouple 1 is across AIN5 and AIN4, and 2 reads a millivolt
+        fake.set_disconnected(4, true);
+        fake.set_input(3, 0.001);
+        adc.check_thermocouples().unwrap();
+
+        assert_eq!(adc.open_circuit(), [false, true, false, false]);
+        assert!(adc.is_open_circuit(1) && !adc.is_open_circuit(2));
+
+        // the burnout sources are off again, with the cold junction selected
+        assert_eq!(fake.register(SYS), 0x43);
+
+        fake.set_disconnected(4, false);
+        adc.check_thermocouples().unwrap();
+
+        assert_eq!(adc.open_circuit(), [false; 4]);
+    }
+
+    #[test]
+    fn burnout_check_skips_other_adcs() {
+        let (fake, mut adc) = initialized(Measurement::DiffSensors);
+        fake.set_disconnected(4, true);
+        let commands = fake.commands().len();
+
+        adc.check_thermocouples().unwrap();
+
+        assert_eq!(fake.commands().len(), commands);
+        assert_eq!(adc.open_circuit(), [false; 3]);
+    }
+
+    #[test]
+    fn direct_reads_return_the_channel_just_read() {
+        let (fake, mut adc) = with_data_ready(Measurement::IPower, Acquisition::Direct);
+
+        // 2.5 V above the input, see single_ended_codes_decode_to_volts
+        fake.set_input(0, 1.25);
+        fake.set_input(1, 0.625);
+
+        for iteration in 0..4 {
+            let (reading, _) = adc.read_and_advance(iteration, iteration + 1).unwrap();
+
+            assert_close(reading, [3.75, 3.125][iteration as usize % 2]);
+            assert_eq!(fake.register(INPMUX), [0x0C, 0x1C][(iteration as usize + 1) % 2]);
+        }
+
+        // shifted out on DRDY, without RDATA
+        assert!(!fake.commands().contains(&0x12));
+    }
+
+    #[test]
+    fn pipelined_reads_return_the_channel_before_the_one_selected() {
+        let (fake, mut adc) = with_data_ready(Measurement::IPower, Acquisition::Pipelined);
+        fake.set_input(0, 1.25);
+        fake.set_input(1, 0.625);
+
+        for iteration in 0..4 {
+            // the transfer that selects the next channel carries the data of
+            // the channel selected before it
+            let (reading, _) = adc.read_and_advance(iteration, iteration + 1).unwrap();
+
+            assert_close(reading, [3.75, 3.125][iteration as usize % 2]);
+            assert_eq!(fake.register(INPMUX), [0x0C, 0x1C][(iteration as usize + 1) % 2]);
+        }
+
+        assert!(!fake.commands().contains(&0x12));
+    }
+
+    #[test]
+    fn settling_discards_conversions_after_a_mux_change() {
+        for (policy, conversions) in [(Settling::Immediate, 1), (Settling::Discard(1), 2), (Settling::Discard(3), 4)] {
+            let (fake, mut adc) = with_data_ready(Measurement::IPower, Acquisition::Command);
+            adc.configure_channels(settling(Measurement::IPower, &[policy; 2]));
+
+            adc.write_iteration(1);
+            let before = rdata(&fake);
+            adc.get_adc_reading(1).unwrap();
+            assert_eq!(rdata(&fake) - before, conversions, "{policy:?}");
+
+            // only the first read after the mux changes settles
+            let before = rdata(&fake);
+            adc.get_adc_reading(1).unwrap();
+            assert_eq!(rdata(&fake) - before, 1, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn settling_delay_waits_from_the_mux_change() {
+        let (fake, mut adc) = with_data_ready(Measurement::IPower, Acquisition::Command);
+        adc.configure_channels(settling(Measurement::IPower, &[Settling::Delay(3000); 2]));
+
+        let selected = std::time::Instant::now();
+        adc.write_iteration(1);
+        let before = rdata(&fake);
+        adc.get_adc_reading(1).unwrap();
+
+        assert!(selected.elapsed() >= std::time::Duration::from_micros(3000));
+        assert_eq!(rdata(&fake) - before, 1);
+    }
+
+    #[test]
+    fn settling_can_be_set_per_channel() {
+        let (fake, mut adc) = with_data_ready(Measurement::IPower, Acquisition::Command);
+        adc.configure_channels(settling(Measurement::IPower, &[Settling::Immediate, Settling::Discard(2)]));
+
+        for (iteration, conversions) in [(0, 1), (1, 3), (2, 1), (3, 3)] {
+            let before = rdata(&fake);
+            adc.read_and_advance(iteration, iteration + 1).unwrap();
+            assert_eq!(rdata(&fake) - before, conversions, "channel {}", iteration % 2);
+        }
+    }
+
+    #[test]
+    fn settling_without_data_ready_lets_conversions_go_by() {
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+        adc.configure_channels(settling(Measurement::Rtd, &[Settling::Discard(2); 2]));
+
+        adc.write_iteration(1);
+        let before = rdata(&fake);
+        adc.get_adc_reading(1).unwrap();
+
+        // the discarded conversions are waited out, not read
+        assert_eq!(rdata(&fake) - before, 1);
+    }
+
+    #[test]
+    fn a_start_pulse_converts_every_adc_once() {
+        let mut adcs: Vec<_> = [Measurement::Rtd, Measurement::Tc1, Measurement::Tc2]
+            .into_iter()
+            .map(|measurement| {
+                let (fake, mut adc) = adc(measurement);
+                adc.set_synchronized(true);
+                adc.initialize().unwrap();
+                adc.write_iteration(1);
+
+                // single-shot, so the START sent while initializing converts
+                // once and stops
+                assert_eq!(fake.register(DATARATE) & 0x20, 0x20);
+                adc.read_conversion().unwrap();
+                assert!(!fake.is_converting());
+
+                (fake, adc)
+            })
+            .collect();
+
+        let starts = |adcs: &[(Rc<FakeAds114s0x>, ADC<FakeAds114s0x>)]| -> Vec<usize> {
+            adcs.iter().map(|(fake, _)| fake.commands().iter().filter(|command| **command == 0x08).count()).collect()
+        };
+
+        let mut previous: Option<Vec<f64>> = None;
+
+        for volts in [0.01, 0.02] {
+            let before = starts(&adcs);
+
+            // channel 1 of the RTD ADC is on AIN3, and of the thermocouple
+            // ADCs on AIN5
+            for (fake, adc) in &mut adcs {
+                fake.set_input(3, volts);
+                fake.set_input(5, volts);
+                adc.settle(1).unwrap();
+                assert!(!fake.is_converting());
+            }
+
+            // the one pulse on the shared pin reaches every ADC
+            let pulsed = std::time::Instant::now();
+            for (fake, adc) in &mut adcs {
+                fake.pulse_start();
+                adc.started(pulsed);
+            }
+
+            let mut readings = Vec::new();
+
+            for (fake, adc) in &mut adcs {
+                let (reading, _) = adc.get_adc_reading(1).unwrap();
+                assert!(!fake.is_converting(), "{:?}", adc.measurement);
+
+                // and nothing more is converted until the next pulse
+                fake.set_input(3, 0.0);
+                fake.set_input(5, 0.0);
+                assert_eq!(adc.get_adc_reading(1).unwrap().0, reading, "{:?}", adc.measurement);
+
+                readings.push(reading);
+            }
+
+            // the pulse alone started the round
+            assert_eq!(starts(&adcs), before);
+
+            if let Some(previous) = previous {
+                assert!(readings.iter().zip(&previous).all(|(reading, previous)| reading != previous));
+            }
+
+            previous = Some(readings);
+        }
+    }
+
+    #[test]
+    fn the_24_bit_part_reads_end_to_end() {
+        let fake = Rc::new(FakeAds114s0x::ads124s0x());
+        let mut adc = ADC::new(Measurement::DiffSensors, fake.clone(), Rc::new(HashMap::new()), Rc::new(HashMap::new()));
+        adc.set_part(Part::Ads124s0x);
+        adc.initialize().unwrap();
+
+        // STATUS and CRC framing the three data bytes
+        assert_eq!(fake.register(SYS) & 0x03, 0x03);
+
+        // 1 mV at gain 32 against 2.5 V, on both sides of zero
+        let code = (0.001 * 32.0 / 2.5 * 8388608.0_f64).round();
+        let volts = code * 2.5 / 8388608.0 / 32.0;
+
+        fake.set_input(4, 0.001);
+        adc.write_iteration(0);
+        assert_eq!(adc.read_conversion().unwrap(), -code as i32);
+        assert!(code > 32768.0, "{code} fits in 16 bits");
+
+        fake.set_input(1, 0.001);
+        adc.write_iteration(2);
+        assert_close(adc.test_read_individual(2).unwrap(), volts);
+
+        // and calibrates with three-byte coefficients
+        fake.set_offset(-70000);
+        let coefficients = adc.calibrate(2, Routine::SelfOffset).unwrap();
+        assert_eq!(coefficients.offset, -70000);
+        assert_eq!(fake.registers()[OFCAL0..OFCAL0 + 3], (-70000_i32).to_le_bytes()[..3]);
+        assert_close(adc.test_read_individual(2).unwrap(), volts);
+    }
+
+    #[test]
+    fn health_reads_the_supplies_temperature_and_reference() {
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+        let configured = fake.registers();
+
+        fake.set_supplies(4.8, 3.2);
+        fake.set_die_temperature(60.0);
+        fake.set_external_reference(2.0);
+
+        let health = adc.read_health().unwrap();
+
+        // within a few LSBs of each monitor at 16 bits
+        assert!((health.avdd - 4.8).abs() < 1e-3, "{}", health.avdd);
+        assert!((health.dvdd - 3.2).abs() < 1e-3, "{}", health.dvdd);
+        // the sensor puts out 129 mV at 25 C and 403 uV/C more above it,
+        // which SAM converts the same way as the cold junctions
+        let sensor = 0.129 + (60.0 - 25.0) * 0.000403;
+        assert!((health.temperature - (sensor * 403.0 - 26.987)).abs() < 0.1, "{}", health.temperature);
+        assert!((health.reference - 2.0).abs() < 1e-3, "{}", health.reference);
+
+        // and the scan's configuration is put back afterwards
+        assert_eq!(fake.register(REF), configured[REF as usize]);
+        assert_eq!(fake.register(SYS), configured[SYS as usize]);
+    }
+
+    #[test]
+    fn health_reports_a_missing_reference_as_nan() {
+        let (fake, mut adc) = initialized(Measurement::Rtd);
+
+        // AVDD, DVDD and die temperature against the internal reference, then
+        // AVDD reading zero against the external one
+        for code in [0x1000, 0x1000, 0x0200, 0] {
+            fake.queue_code(code);
+        }
+
+        let health = adc.read_health().unwrap();
+
+        assert!(health.avdd.is_finite());
+        assert!(health.reference.is_nan());
+    }
+
+    #[test]
+    fn transducer_channels_take_no_transfer() {
+        let (_, mut adc) = adc(Measurement::CurrentLoopPt);
+        let mut transducers = vec![None; 6];
+        transducers[1] = Some(Transducer {
+            sense_resistor: 250.0,
+            current_span: [4.0, 20.0],
+            pressure_range: [0.0, 1000.0],
+            units: PressureUnit::Psi,
+        });
+        adc.configure_transducers(transducers);
+
+        let transfer = Transfer::Linear { slope: 2.0, offset: 0.0 };
+        assert!(matches!(adc.set_transfer(1, transfer.clone()), Err(SamError::InvalidTransfer(_))));
+        assert!(adc.set_transfer(0, transfer).is_ok());
+    }
+}
@@ -0,0 +1,547 @@
+//! Board definition: which GPIO every chip select, data ready line, valve and
+//! LED on the SAM board is wired to, and which pins carry the SPI bus.
+//!
+//! The definition is a TOML file (see board.toml at the root of the repo)
+//! naming each pin by its controller and bit. It is checked on load so that a
+//! pin can't be claimed twice and a channel can't be defined twice.
+
+use serde::Deserialize;
+use std::{collections::{HashMap, HashSet}, fs, sync::Arc};
+
+use crate::{
+    adc::{registers::{Gain, IdacMagnitude, Input, Part, ReferenceSource, SampleRate}, Acquisition, ChannelConfig, Measurement, Settling},
+    calibration::Routine,
+    error::SamError,
+    gpio::{Gpio, Pin},
+    rtd::{RtdConfig, Wiring},
+    scan::Scan,
+    transducer::Transducer,
+};
+
+const DEFAULT_BOARD: &str = include_str!("../board.toml");
+
+const NUM_CONTROLLERS: usize = 4;
+const PINS_PER_CONTROLLER: usize = 32;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct BoardPin {
+    pub controller: usize,
+    pub bit: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdcPins {
+    pub measurement: Measurement,
+    pub cs: BoardPin,
+    pub drdy: Option<BoardPin>,
+    /// Which converter is fitted.
+    #[serde(default)]
+    pub part: Part,
+    /// How conversions are read out while polling.
+    #[serde(default)]
+    pub acquisition: Acquisition,
+    /// Gain and data rate of every channel unless the channel overrides it.
+    pub gain: Option<Gain>,
+    pub data_rate: Option<SampleRate>,
+    /// What every channel waits for after a mux change before it is read.
+    pub settling: Option<Settling>,
+    /// Times a second every channel is read and sent to the flight computer.
+    pub scan_rate: Option<f64>,
+    /// Calibration run on every channel each time SAM starts.
+    pub calibrate: Option<Routine>,
+    #[serde(rename = "channel", default)]
+    pub channels: Vec<AdcChannel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdcChannel {
+    /// Position of the channel in the ADC's scan.
+    pub index: usize,
+    pub gain: Option<Gain>,
+    pub data_rate: Option<SampleRate>,
+    pub settling: Option<Settling>,
+    pub scan_rate: Option<f64>,
+    pub calibrate: Option<Routine>,
+    /// Wiring and excitation, for channels of an RTD ADC.
+    pub rtd: Option<RtdChannel>,
+    /// Pressure transducer, for channels of the current-loop ADC.
+    pub transducer: Option<Transducer>,
+}
+
+/// Overrides of the board's RTD wiring. Inputs are AIN numbers.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RtdChannel {
+    /// 2, 3 or 4 wires.
+    pub wiring: Option<Wiring>,
+    /// Microamps.
+    pub excitation: Option<IdacMagnitude>,
+    pub positive: Option<Input>,
+    pub negative: Option<Input>,
+    pub idac1: Option<Input>,
+    pub idac2: Option<Input>,
+    pub reference: Option<ReferenceSource>,
+    pub reference_resistor: Option<f64>,
+    pub lead_resistance: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpiPins {
+    pub sclk: BoardPin,
+    pub miso: BoardPin,
+    pub mosi: BoardPin,
+    pub cs: BoardPin,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Valve {
+    pub channel: u32,
+    pub pin: BoardPin,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Led {
+    pub channel: u32,
+    pub pin: BoardPin,
+    /// Name under /sys/class/leds when the LED is owned by the kernel.
+    pub sysfs: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Board {
+    pub spi: SpiPins,
+    #[serde(rename = "adc")]
+    pub adcs: Vec<AdcPins>,
+    #[serde(default)]
+    pub idle_cs: Vec<BoardPin>,
+    /// START/SYNC pin shared by every ADC. When there is one, the ADCs
+    /// convert once per pulse on it rather than continuously.
+    pub start: Option<BoardPin>,
+    #[serde(rename = "valve", default)]
+    pub valves: Vec<Valve>,
+    #[serde(rename = "led", default)]
+    pub leds: Vec<Led>,
+}
+
+impl BoardPin {
+    pub fn get(&self, controllers: &[Arc<Gpio>]) -> Pin {
+        controllers[self.controller].get_pin(self.bit)
+    }
+}
+
+impl AdcPins {
+    /// Gain, data rate and settling of every channel in scan order, falling
+    /// back to the ADC's settings and then to the measurement's defaults.
+    pub fn channel_configs(&self) -> Vec<ChannelConfig> {
+        let default = self.measurement.default_channel_config();
+
+        (0..self.measurement.num_channels())
+            .map(|index| {
+                let channel = self.channels.iter().find(|channel| channel.index == index);
+
+                ChannelConfig {
+                    gain: channel.and_then(|channel| channel.gain).or(self.gain).unwrap_or(default.gain),
+                    data_rate: channel
+                        .and_then(|channel| channel.data_rate)
+                        .or(self.data_rate)
+                        .unwrap_or(default.data_rate),
+                    settling: channel
+                        .and_then(|channel| channel.settling)
+                        .or(self.settling)
+                        .unwrap_or(default.settling),
+                }
+            })
+            .collect()
+    }
+
+    /// Scan rate of every channel in scan order, if it has one.
+    pub fn scan_rates(&self) -> Vec<Option<f64>> {
+        (0..self.measurement.num_channels())
+            .map(|index| {
+                let channel = self.channels.iter().find(|channel| channel.index == index);
+                channel.and_then(|channel| channel.scan_rate).or(self.scan_rate)
+            })
+            .collect()
+    }
+
+    /// RTD wiring of every channel in scan order, falling back to the board's
+    /// own harness.
+    pub fn rtd_configs(&self) -> Vec<RtdConfig> {
+        (0..self.measurement.num_channels())
+            .map(|index| {
+                let default = RtdConfig::default_for(index);
+                let Some(rtd) = self.channels
+                    .iter()
+                    .find(|channel| channel.index == index)
+                    .and_then(|channel| channel.rtd.as_ref())
+                else {
+                    return default;
+                };
+
+                RtdConfig {
+                    wiring: rtd.wiring.unwrap_or(default.wiring),
+                    excitation: rtd.excitation.unwrap_or(default.excitation),
+                    positive: rtd.positive.unwrap_or(default.positive),
+                    negative: rtd.negative.unwrap_or(default.negative),
+                    idac1: rtd.idac1.unwrap_or(default.idac1),
+                    idac2: rtd.idac2.unwrap_or(default.idac2),
+                    reference: rtd.reference.unwrap_or(default.reference),
+                    reference_resistor: rtd.reference_resistor.or(default.reference_resistor),
+                    lead_resistance: rtd.lead_resistance.unwrap_or(default.lead_resistance),
+                }
+            })
+            .collect()
+    }
+
+    /// Pressure transducer of every channel in scan order, if it has one.
+    pub fn transducers(&self) -> Vec<Option<Transducer>> {
+        (0..self.measurement.num_channels())
+            .map(|index| {
+                let channel = self.channels.iter().find(|channel| channel.index == index);
+                channel.and_then(|channel| channel.transducer)
+            })
+            .collect()
+    }
+
+    /// Channels to calibrate at startup and the routine for each.
+    pub fn startup_calibrations(&self) -> Vec<(usize, Routine)> {
+        (0..self.measurement.num_channels())
+            .filter(|index| !self.measurement.is_cold_junction(*index))
+            .filter_map(|index| {
+                let channel = self.channels.iter().find(|channel| channel.index == index);
+                let routine = channel.and_then(|channel| channel.calibrate).or(self.calibrate)?;
+
+                Some((index, routine))
+            })
+            .collect()
+    }
+}
+
+impl Board {
+    /// Loads the board named by SAM_BOARD, or the built-in definition when it
+    /// isn't set.
+    pub fn load() -> Result<Board, SamError> {
+        match std::env::var("SAM_BOARD") {
+            Ok(path) => Board::from_file(&path),
+            Err(_) => Board::parse(DEFAULT_BOARD),
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<Board, SamError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|source| SamError::BoardRead { path: path.to_string(), source })?;
+
+        Board::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Board, SamError> {
+        let board: Board = toml::from_str(contents).map_err(SamError::BoardParse)?;
+        board.validate()?;
+        Ok(board)
+    }
+
+    pub fn adc(&self, measurement: Measurement) -> Option<&AdcPins> {
+        self.adcs.iter().find(|adc| adc.measurement == measurement)
+    }
+
+    pub fn valve(&self, channel: u32) -> Option<&Valve> {
+        self.valves.iter().find(|valve| valve.channel == channel)
+    }
+
+    pub fn led(&self, channel: u32) -> Option<&Led> {
+        self.leds.iter().find(|led| led.channel == channel)
+    }
+
+    /// Every chip select on the SPI bus, including idle ones.
+    pub fn chip_selects(&self) -> impl Iterator<Item = BoardPin> + '_ {
+        self.adcs.iter().map(|adc| adc.cs).chain(self.idle_cs.iter().copied())
+    }
+
+    fn validate(&self) -> Result<(), SamError> {
+        let mut owners: HashMap<BoardPin, String> = HashMap::new();
+        let mut claim = |pin: BoardPin, owner: String| {
+            if pin.controller >= NUM_CONTROLLERS || pin.bit >= PINS_PER_CONTROLLER {
+                return Err(SamError::InvalidBoard(format!(
+                    "{owner} uses nonexistent pin {}/{}", pin.controller, pin.bit
+                )));
+            }
+
+            match owners.insert(pin, owner.clone()) {
+                Some(other) => Err(SamError::InvalidBoard(format!(
+                    "{owner} and {other} both use pin {}/{}", pin.controller, pin.bit
+                ))),
+                None => Ok(()),
+            }
+        };
+
+        claim(self.spi.sclk, String::from("SPI clock"))?;
+        claim(self.spi.miso, String::from("SPI MISO"))?;
+        claim(self.spi.mosi, String::from("SPI MOSI"))?;
+        claim(self.spi.cs, String::from("SPI chip select"))?;
+
+        let mut measurements = HashSet::new();
+
+        for adc in &self.adcs {
+            if !measurements.insert(adc.measurement) {
+                return Err(SamError::InvalidBoard(format!("{:?} ADC is defined twice", adc.measurement)));
+            }
+
+            claim(adc.cs, format!("{:?} chip select", adc.measurement))?;
+
+            if let Some(drdy) = adc.drdy {
+                claim(drdy, format!("{:?} data ready", adc.measurement))?;
+            } else if adc.acquisition != Acquisition::Command {
+                return Err(SamError::InvalidBoard(format!(
+                    "{:?} ADC has no data ready pin to read {:?} on", adc.measurement, adc.acquisition
+                )));
+            }
+
+            if adc.drdy.is_none() && adc.channel_configs().iter().any(|channel| channel.settling == Settling::DataReady) {
+                return Err(SamError::InvalidBoard(format!(
+                    "{:?} ADC has no data ready pin to settle on", adc.measurement
+                )));
+            }
+
+            let mut channels = HashSet::new();
+
+            for rate in adc.scan_rates().into_iter().flatten() {
+                if !(rate.is_finite() && rate > 0.0) {
+                    return Err(SamError::InvalidBoard(format!(
+                        "{:?} ADC has scan rate {rate}, which isn't a positive number", adc.measurement
+                    )));
+                }
+            }
+
+            for channel in &adc.channels {
+                if channel.index >= adc.measurement.num_channels() {
+                    return Err(SamError::InvalidBoard(format!(
+                        "{:?} ADC has no channel {}", adc.measurement, channel.index
+                    )));
+                }
+
+                if adc.measurement.is_cold_junction(channel.index) {
+                    return Err(SamError::InvalidBoard(format!(
+                        "{:?} channel {} is the cold junction sensor and can't be configured", adc.measurement, channel.index
+                    )));
+                }
+
+                if let Some(rtd) = &channel.rtd {
+                    if adc.measurement != Measurement::Rtd {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has RTD wiring, but isn't an RTD", adc.measurement, channel.index
+                        )));
+                    }
+
+                    let resistances = [rtd.reference_resistor, rtd.lead_resistance];
+
+                    if resistances.into_iter().flatten().any(|ohms| !(ohms.is_finite() && ohms >= 0.0)) {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has an invalid resistance, expected ohms at or above 0", adc.measurement, channel.index
+                        )));
+                    }
+                }
+
+                if let Some(transducer) = &channel.transducer {
+                    if adc.measurement != Measurement::CurrentLoopPt {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has a transducer, but isn't a current loop", adc.measurement, channel.index
+                        )));
+                    }
+
+                    let [low, high] = transducer.current_span;
+                    let [bottom, top] = transducer.pressure_range;
+
+                    if !(transducer.sense_resistor.is_finite() && transducer.sense_resistor > 0.0) {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has a sense resistor of {} ohms, expected more than 0", adc.measurement, channel.index, transducer.sense_resistor
+                        )));
+                    }
+
+                    if !(low.is_finite() && high.is_finite() && low >= 0.0 && high > low) {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has current span {low}-{high} mA, expected it to rise from 0 or above", adc.measurement, channel.index
+                        )));
+                    }
+
+                    if !(bottom.is_finite() && top.is_finite() && bottom != top) {
+                        return Err(SamError::InvalidBoard(format!(
+                            "{:?} channel {} has an empty pressure range {bottom}-{top}", adc.measurement, channel.index
+                        )));
+                    }
+                }
+
+                if !channels.insert(channel.index) {
+                    return Err(SamError::InvalidBoard(format!(
+                        "{:?} channel {} is defined twice", adc.measurement, channel.index
+                    )));
+                }
+            }
+        }
+
+        for pin in &self.idle_cs {
+            claim(*pin, String::from("idle chip select"))?;
+        }
+
+        if let Some(start) = self.start {
+            claim(start, String::from("ADC START/SYNC"))?;
+        }
+
+        let mut valve_channels = HashSet::new();
+
+        for valve in &self.valves {
+            if !valve_channels.insert(valve.channel) {
+                return Err(SamError::InvalidBoard(format!("valve {} is defined twice", valve.channel)));
+            }
+
+            claim(valve.pin, format!("valve {}", valve.channel))?;
+        }
+
+        let mut led_channels = HashSet::new();
+
+        for led in &self.leds {
+            if !led_channels.insert(led.channel) {
+                return Err(SamError::InvalidBoard(format!("LED {} is defined twice", led.channel)));
+            }
+
+            claim(led.pin, format!("LED {}", led.channel))?;
+        }
+
+        // the scan rates have to make a schedule
+        Scan::new(self)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPI: &str = r#"
+        [spi]
+        sclk = { controller = 0, bit = 2 }
+        miso = { controller = 0, bit = 3 }
+        mosi = { controller = 0, bit = 4 }
+        cs = { controller = 0, bit = 5 }
+    "#;
+
+    fn parse(rest: &str) -> Result<Board, SamError> {
+        Board::parse(&format!("{SPI}\n{rest}"))
+    }
+
+    fn invalid(rest: &str) -> String {
+        match parse(rest) {
+            Err(SamError::InvalidBoard(message)) => message,
+            other => panic!("expected an invalid board, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn the_built_in_board_is_valid() {
+        let board = Board::parse(DEFAULT_BOARD).unwrap();
+
+        assert_eq!(board.adcs.len(), 9);
+        assert_eq!(board.valve(1).unwrap().pin, BoardPin { controller: 0, bit: 8 });
+    }
+
+    #[test]
+    fn a_pin_used_as_a_chip_select_and_a_valve_is_rejected() {
+        let message = invalid(r#"
+            [[adc]]
+            measurement = "Rtd"
+            cs = { controller = 2, bit = 11 }
+
+            [[valve]]
+            channel = 1
+            pin = { controller = 2, bit = 11 }
+        "#);
+
+        assert!(message.contains("valve 1 and Rtd chip select both use pin 2/11"), "{message}");
+    }
+
+    #[test]
+    fn a_channel_defined_twice_is_rejected() {
+        let message = invalid(r#"
+            [[adc]]
+            measurement = "IPower"
+            cs = { controller = 2, bit = 15 }
+
+            [[adc.channel]]
+            index = 1
+            gain = 2
+
+            [[adc.channel]]
+            index = 1
+            gain = 4
+        "#);
+
+        assert!(message.contains("IPower channel 1 is defined twice"), "{message}");
+    }
+
+    #[test]
+    fn a_valve_channel_defined_twice_is_rejected() {
+        let message = invalid(r#"
+            [[adc]]
+            measurement = "Rtd"
+            cs = { controller = 2, bit = 11 }
+
+            [[valve]]
+            channel = 3
+            pin = { controller = 2, bit = 17 }
+
+            [[valve]]
+            channel = 3
+            pin = { controller = 2, bit = 25 }
+        "#);
+
+        assert!(message.contains("valve 3 is defined twice"), "{message}");
+    }
+
+    #[test]
+    fn a_pin_off_the_header_is_rejected() {
+        let message = invalid(r#"
+            [[adc]]
+            measurement = "Tc1"
+            cs = { controller = 0, bit = 32 }
+        "#);
+        assert!(message.contains("Tc1 chip select uses nonexistent pin 0/32"), "{message}");
+
+        let message = invalid(r#"
+            [[adc]]
+            measurement = "Rtd"
+            cs = { controller = 2, bit = 11 }
+
+            [[led]]
+            channel = 0
+            pin = { controller = 4, bit = 0 }
+        "#);
+        assert!(message.contains("LED 0 uses nonexistent pin 4/0"), "{message}");
+    }
+
+    #[test]
+    fn a_scan_rate_that_isnt_positive_is_rejected() {
+        for rate in ["0.0", "-10.0", "nan", "inf"] {
+            let message = invalid(&format!(r#"
+                [[adc]]
+                measurement = "Rtd"
+                cs = {{ controller = 2, bit = 11 }}
+                scan_rate = {rate}
+            "#));
+
+            assert!(message.contains("isn't a positive number"), "{rate}: {message}");
+        }
+    }
+
+    #[test]
+    fn malformed_toml_is_a_parse_error() {
+        assert!(matches!(parse("[[adc]]\nmeasurement = \"Nonsense\""), Err(SamError::BoardParse(_))));
+        assert!(matches!(parse("[[valve]]\nchannel = 1\npin = 3"), Err(SamError::BoardParse(_))));
+    }
+}
@@ -0,0 +1,313 @@
+//! ADC offset and gain calibration, and the on-disk record of its results.
+//!
+//! https://www.ti.com/lit/ds/symlink/ads114s08.pdf
+//! Section 9.3.10 covers the three calibration commands. SFOCAL shorts the
+//! inputs internally and measures the offset, SYOCAL measures the offset with
+//! the system's zero input applied, and SYGCAL measures the gain error with the
+//! system's full-scale input applied. The ADC stores the results in OFCAL and
+//! FSCAL, which apply to every conversion until they are overwritten.
+//!
+//! Every calibration SAM runs is saved to <dir>/calibration.toml along with the
+//! routine that produced it and when, and is written back into the ADC on
+//! boot. <dir> is SAM_CALIBRATION_DIR, or /var/lib/sam when unset. Calibration
+//! can be requested while SAM is running by writing a request.toml, e.g.
+//!
+//!     [[calibrate]]
+//!     measurement = "Tc1"
+//!     channel = 2
+//!     routine = "system_offset"
+//!
+//! which SAM picks up, runs and deletes, or by sending a postcard-encoded
+//! `Request` to UDP port 8379 (see src/command.rs), e.g. from the flight
+//! computer.
+
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+use crate::{adc::{registers::Part, Measurement}, error::SamError};
+
+const DEFAULT_DIR: &str = "/var/lib/sam";
+const RECORD_FILE: &str = "calibration.toml";
+const REQUEST_FILE: &str = "request.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Routine {
+    SelfOffset,
+    SystemOffset,
+    SystemGain,
+}
+
+impl Routine {
+    pub fn command(&self) -> u8 {
+        match self {
+            Routine::SystemOffset => 0x16,
+            Routine::SystemGain => 0x17,
+            Routine::SelfOffset => 0x19,
+        }
+    }
+}
+
+/// Contents of the OFCAL and FSCAL registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coefficients {
+    pub offset: i32,
+    pub full_scale: u32,
+}
+
+impl Coefficients {
+    /// What the ADC comes out of reset with: no offset, unity gain.
+    pub const fn reset(part: Part) -> Coefficients {
+        Coefficients { offset: 0, full_scale: 1 << (part.resolution() - 2) }
+    }
+
+    /// OFCAL then FSCAL, each least significant byte first and as wide as
+    /// the part's registers.
+    pub fn to_bytes(&self, part: Part) -> Vec<u8> {
+        let width = part.word_bytes();
+        let mut bytes = self.offset.to_le_bytes()[..width].to_vec();
+        bytes.extend_from_slice(&self.full_scale.to_le_bytes()[..width]);
+
+        bytes
+    }
+
+    pub fn from_bytes(part: Part, bytes: &[u8]) -> Coefficients {
+        let (offset, full_scale) = bytes.split_at(part.word_bytes());
+
+        // OFCAL is two's complement, so sign extend it from its top bit
+        let shift = 32 - 8 * offset.len() as u32;
+
+        Coefficients {
+            offset: (from_le(offset) << shift) as i32 >> shift,
+            full_scale: from_le(full_scale),
+        }
+    }
+}
+
+fn from_le(bytes: &[u8]) -> u32 {
+    bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32)
+}
+
+/// The latest calibration of one channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Record {
+    pub measurement: Measurement,
+    pub channel: usize,
+    pub routine: Routine,
+    /// RFC 3339 time the routine was run.
+    pub date: String,
+    /// Part the coefficients were measured on, records from before 24-bit
+    /// parts were supported are all ADS114S0x.
+    #[serde(default)]
+    pub part: Part,
+    pub offset: i32,
+    pub full_scale: u32,
+}
+
+impl Record {
+    pub fn coefficients(&self) -> Coefficients {
+        Coefficients { offset: self.offset, full_scale: self.full_scale }
+    }
+}
+
+/// A routine to run on one channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Request {
+    pub measurement: Measurement,
+    pub channel: usize,
+    pub routine: Routine,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RecordFile {
+    #[serde(rename = "channel", default)]
+    records: Vec<Record>,
+}
+
+#[derive(Deserialize)]
+struct RequestFile {
+    #[serde(default)]
+    calibrate: Vec<Request>,
+}
+
+pub struct CalibrationStore {
+    dir: PathBuf,
+    records: Vec<Record>,
+}
+
+/// SAM_CALIBRATION_DIR, or the default directory when it isn't set.
+pub fn dir_from_env() -> PathBuf {
+    std::env::var_os("SAM_CALIBRATION_DIR").map_or_else(|| PathBuf::from(DEFAULT_DIR), PathBuf::from)
+}
+
+impl CalibrationStore {
+    /// Opens the store in SAM_CALIBRATION_DIR, or the default directory when
+    /// it isn't set.
+    pub fn from_env() -> Result<CalibrationStore, SamError> {
+        CalibrationStore::open(dir_from_env())
+    }
+
+    /// Loads the records in `dir`. A directory without any is an empty store.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<CalibrationStore, SamError> {
+        let dir = dir.into();
+        let path = dir.join(RECORD_FILE);
+
+        let records = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let file: RecordFile = toml::from_str(&contents)
+                    .map_err(|source| SamError::CalibrationParse { path: path.display().to_string(), source })?;
+
+                file.records
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(source) => return Err(SamError::Calibration { path: path.display().to_string(), source }),
+        };
+
+        Ok(CalibrationStore { dir, records })
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Records the result of a routine, replacing the channel's previous
+    /// calibration, and saves the store.
+    pub fn save(&mut self, measurement: Measurement, channel: usize, part: Part, routine: Routine, coefficients: Coefficients) -> Result<(), SamError> {
+        let record = Record {
+            measurement,
+            channel,
+            routine,
+            date: chrono::Utc::now().to_rfc3339(),
+            part,
+            offset: coefficients.offset,
+            full_scale: coefficients.full_scale,
+        };
+
+        self.records.retain(|record| record.measurement != measurement || record.channel != channel);
+        self.records.push(record);
+
+        let path = self.dir.join(RECORD_FILE);
+        let file = RecordFile { records: self.records.clone() };
+        let contents = toml::to_string(&file)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+
+        // write then rename, so a crash mid-write can't lose every record
+        let temporary = path.with_extension("toml.tmp");
+
+        fs::create_dir_all(&self.dir)
+            .and(contents)
+            .and_then(|contents| fs::write(&temporary, contents))
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|source| SamError::Calibration { path: path.display().to_string(), source })
+    }
+
+    /// Reads and deletes any pending calibration requests.
+    pub fn take_requests(&self) -> Result<Vec<Request>, SamError> {
+        let path = self.dir.join(REQUEST_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => return Err(SamError::Calibration { path: path.display().to_string(), source }),
+        };
+
+        // removed before parsing so a bad request isn't retried forever
+        fs::remove_file(&path)
+            .map_err(|source| SamError::Calibration { path: path.display().to_string(), source })?;
+
+        let file: RequestFile = toml::from_str(&contents)
+            .map_err(|source| SamError::CalibrationParse { path: path.display().to_string(), source })?;
+
+        Ok(file.calibrate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory for one test's store
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sam-calibration-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn saved_records_load_back() {
+        let dir = store_dir("round-trip");
+        let mut store = CalibrationStore::open(&dir).unwrap();
+
+        let offset = Coefficients { offset: -37, full_scale: 0x4012 };
+        let gain = Coefficients { offset: 5, full_scale: 0x3FF0 };
+        store.save(Measurement::Tc1, 2, Part::Ads114s0x, Routine::SystemOffset, offset).unwrap();
+        store.save(Measurement::Rtd, 1, Part::Ads114s0x, Routine::SystemGain, gain).unwrap();
+
+        // a second routine on the same channel replaces the first
+        let offset = Coefficients { offset: -40, full_scale: 0x4012 };
+        store.save(Measurement::Tc1, 2, Part::Ads114s0x, Routine::SelfOffset, offset).unwrap();
+
+        let loaded = CalibrationStore::open(&dir).unwrap();
+        let records = loaded.records();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].measurement, records[0].channel, records[0].routine), (Measurement::Rtd, 1, Routine::SystemGain));
+        assert_eq!(records[0].coefficients(), gain);
+        assert_eq!((records[1].measurement, records[1].channel, records[1].routine), (Measurement::Tc1, 2, Routine::SelfOffset));
+        assert_eq!(records[1].coefficients(), offset);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_missing_store_is_empty() {
+        let dir = store_dir("missing");
+
+        assert!(CalibrationStore::open(&dir).unwrap().records().is_empty());
+        assert!(CalibrationStore::open(&dir).unwrap().take_requests().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_corrupt_store_is_a_parse_error() {
+        let dir = store_dir("corrupt");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(RECORD_FILE), "[[channel]]\nmeasurement = \"Tc1\"\noffset = \"lots\"\n").unwrap();
+
+        assert!(matches!(CalibrationStore::open(&dir), Err(SamError::CalibrationParse { .. })));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requests_are_taken_once_even_when_invalid() {
+        let dir = store_dir("requests");
+        let store = CalibrationStore::open(&dir).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join(REQUEST_FILE), "[[calibrate]]\nmeasurement = \"Tc2\"\nchannel = 3\nroutine = \"system_gain\"\n").unwrap();
+        let requests = store.take_requests().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].measurement, requests[0].channel, requests[0].routine), (Measurement::Tc2, 3, Routine::SystemGain));
+        assert!(store.take_requests().unwrap().is_empty());
+
+        fs::write(dir.join(REQUEST_FILE), "[[calibrate]]\nroutine = \"sideways\"\n").unwrap();
+        assert!(matches!(store.take_requests(), Err(SamError::CalibrationParse { .. })));
+        assert!(!dir.join(REQUEST_FILE).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn coefficients_round_trip_through_register_bytes() {
+        for part in [Part::Ads114s0x, Part::Ads124s0x] {
+            for coefficients in [Coefficients::reset(part), Coefficients { offset: -2, full_scale: 0x3FFE }] {
+                let bytes = coefficients.to_bytes(part);
+
+                assert_eq!(bytes.len(), 2 * part.word_bytes());
+                assert_eq!(Coefficients::from_bytes(part, &bytes), coefficients, "{part:?}");
+            }
+        }
+    }
+}
@@ -4,11 +4,16 @@ use std::fs::File;
 
 use std::io::Write;
 use std::net::UdpSocket;
-use std::sync::Arc;
+use std::sync::{mpsc::Sender, Arc};
+use crate::board::Board;
+use crate::calibration::Request;
 use crate::gpio::{Gpio, PinMode::Output, PinValue::{High, Low}};
 
+// calibration requests have their own port, since they aren't a
+// SamControlMessage
+pub const CALIBRATION_PORT: u16 = 8379;
 
-pub fn begin(gpio_controllers: Vec<Arc<Gpio>>) {
+pub fn begin(gpio_controllers: Vec<Arc<Gpio>>, board: Arc<Board>) {
     let socket = UdpSocket::bind("0.0.0.0:8378").expect("Cannot bind to socket");
     let mut buf = [0; 65536];
     loop {
@@ -18,161 +23,73 @@ pub fn begin(gpio_controllers: Vec<Arc<Gpio>>) {
         println!("{:#?}", deserialized_result);
         match deserialized_result {
             Ok(message) => {
-                execute(message, gpio_controllers.clone());
+                execute(message, gpio_controllers.clone(), &board);
             },
             Err(_error) => fail!("Bad command message from flight computer"),
         };
     }
 }
 
-fn execute(command: SamControlMessage, gpio_controllers: Vec<Arc<Gpio>>) {
+/// Forwards calibration requests to the state thread, which runs them between
+/// scan frames.
+pub fn begin_calibration(requests: Sender<Request>) {
+    let socket = UdpSocket::bind(("0.0.0.0", CALIBRATION_PORT)).expect("Cannot bind to calibration socket");
+    let mut buf = [0; 1024];
+    loop {
+        let (num_bytes, _src_addr) = socket.recv_from(&mut buf).expect("no data received");
+
+        match postcard::from_bytes::<Request>(&buf[..num_bytes]) {
+            Ok(request) => {
+                if requests.send(request).is_err() {
+                    fail!("State thread is gone, dropping calibration request");
+                }
+            }
+            Err(_error) => fail!("Bad calibration request"),
+        };
+    }
+}
+
+fn execute(command: SamControlMessage, gpio_controllers: Vec<Arc<Gpio>>, board: &Board) {
     match command {
-       SamControlMessage::SetLed { channel, on } => {
-            match on {
-                true => match channel {
-                    0 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr0/brightness")
-                            .unwrap();
-                        file.write(b"1").expect("Failed to write");
-                    }
-                    1 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr1/brightness")
-                            .unwrap();
-                        file.write(b"1").expect("Failed to write");
-                    }
-                    2 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr2/brightness")
-                            .unwrap();
-                        file.write(b"1").expect("Failed to write");
-                    }
-                    3 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr3/brightness")
-                            .unwrap();
-                        file.write(b"1").expect("Failed to write");
-                    }
-                    _ => println!("Error"),
-                },
-                false => match channel {
-                    0 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr0/brightness")
-                            .unwrap();
-                        file.write(b"0").expect("Failed to write");
-                    }
-                    1 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr1/brightness")
-                            .unwrap();
-                        file.write(b"0").expect("Failed to write");
-                    }
-                    2 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr2/brightness")
-                            .unwrap();
-                        file.write(b"0").expect("Failed to write");
-                    }
-                    3 => {
-                        let mut file: File = std::fs::OpenOptions::new()
-                            .write(true)
-                            .truncate(true)
-                            .open("/sys/class/leds/beaglebone:green:usr3/brightness")
-                            .unwrap();
-                        file.write(b"0").expect("Failed to write");
+        SamControlMessage::SetLed { channel, on } => {
+            let Some(led) = board.led(channel) else {
+                fail!("Invalid LED channel {}", channel);
+                return;
+            };
+
+            match &led.sysfs {
+                Some(name) => {
+                    let mut file: File = std::fs::OpenOptions::new()
+                        .write(true)
+                        .truncate(true)
+                        .open(format!("/sys/class/leds/{}/brightness", name))
+                        .unwrap();
+                    file.write_all(if on { b"1" } else { b"0" }).expect("Failed to write");
+                }
+                None => {
+                    let pin = led.pin.get(&gpio_controllers);
+
+                    if let Err(error) = pin.mode(Output).and_then(|()| pin.digital_write(if on { High } else { Low })) {
+                        fail!("Could not set LED {}: {}", channel, error);
                     }
-                    _ => println!("Error"),
-                },
+                }
             }
         }
 
         SamControlMessage::ActuateValve { channel, powered } => {
-            match powered {
-                true => match channel {
-                    1 => {
-                        let pin = gpio_controllers[0].get_pin(8);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    2 => {
-                        let pin = gpio_controllers[2].get_pin(16);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    3 => {
-                        let pin = gpio_controllers[2].get_pin(17);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    4 => {
-                        let pin = gpio_controllers[2].get_pin(25);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    5 => {
-                        let pin = gpio_controllers[2].get_pin(1);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    6 => {
-                        let pin = gpio_controllers[1].get_pin(14);
-                        pin.mode(Output);
-                        pin.digital_write(High);
-                    }
-                    _ => fail!("Invalid channel number, could not open valve"),
-                },
-                false => match channel {
-                    1 => {
-                        let pin = gpio_controllers[0].get_pin(8);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    2 => {
-                        let pin = gpio_controllers[2].get_pin(16);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    3 => {
-                        let pin = gpio_controllers[2].get_pin(17);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    4 => {
-                        let pin = gpio_controllers[2].get_pin(25);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    5 => {
-                        let pin = gpio_controllers[2].get_pin(1);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    6 => {
-                        let pin = gpio_controllers[1].get_pin(14);
-                        pin.mode(Output);
-                        pin.digital_write(Low);
-                    }
-                    _ => fail!("Invalid channel number, could not close valve"),
-                },
-                
-            }
+            let Some(valve) = board.valve(channel) else {
+                match powered {
+                    true => fail!("Invalid channel number, could not open valve"),
+                    false => fail!("Invalid channel number, could not close valve"),
+                }
+                return;
+            };
+
+            let pin = valve.pin.get(&gpio_controllers);
 
+            if let Err(error) = pin.mode(Output).and_then(|()| pin.digital_write(if powered { High } else { Low })) {
+                fail!("Could not actuate valve {}: {}", channel, error);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,48 @@
+//! Data points sent to the flight computer, and the channels they go out on.
+//!
+//! Sensor channels keep the numbers their measurement has always used. The
+//! rest are offset clear of them:
+//!
+//!  - 100: board health, four per ADC in board order (AVDD, DVDD and the
+//!    reference as rail voltages, then the die temperature in kelvin).
+//!  - 200 plus the channel's own number: the raw reading behind a converted
+//!    channel, e.g. the volts across a pressure transducer's sense resistor.
+//!
+//! A channel whose ADC is faulted, or whose sample was rejected, is sent as
+//! NaN.
+//!
+//! What isn't a reading goes out once a second in a separate [`Diagnostics`]
+//! message on its own port: each ADC's rejected sample counts by cause, and
+//! which of its channels the last burnout check found open.
+
 use std::borrow::Cow;
 
 use common::comm::DataMessage;
-use common::comm::DataPoint;
+use common::comm::{ChannelType, DataPoint};
+use serde::{Deserialize, Serialize};
 use crate::adc;
 
+/// What SAM knows about one ADC that isn't a measurement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdcDiagnostics {
+    pub measurement: adc::Measurement,
+    /// Samples rejected since startup, by cause.
+    pub rejected: adc::RejectedSamples,
+    /// Per channel, whether the last burnout check found it open. Always
+    /// false on ADCs without thermocouples.
+    pub open_circuit: Vec<bool>,
+}
+
+/// Diagnostics of every ADC on the board.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub board_id: String,
+    pub adcs: Vec<AdcDiagnostics>,
+}
+
+const HEALTH_CHANNEL_BASE: u32 = 100;
+
+const RAW_CHANNEL_BASE: u32 = 200;
 
 pub fn serialize_data(board_id: String, data_points: &Vec<DataPoint>) -> Result<Vec<u8>, postcard::Error> {
     let data_message = DataMessage::Sam(board_id, Cow::Borrowed(data_points));
@@ -11,6 +50,10 @@ pub fn serialize_data(board_id: String, data_points: &Vec<DataPoint>) -> Result<
     data_serialized
 }
 
+pub fn serialize_diagnostics(diagnostics: &Diagnostics) -> Result<Vec<u8>, postcard::Error> {
+    postcard::to_allocvec(diagnostics)
+}
+
 pub fn generate_data_point(data: f64, timestamp: f64, iteration: u64, measurement: adc::Measurement) -> DataPoint {
     let data_point = DataPoint {
         value: data,
@@ -22,6 +65,35 @@ pub fn generate_data_point(data: f64, timestamp: f64, iteration: u64, measuremen
     return data_point;
 }
 
+/// The raw reading behind a channel sent in engineering units, e.g. the volts
+/// across a pressure transducer's sense resistor, on RAW_CHANNEL_BASE plus the
+/// channel's own number.
+pub fn generate_raw_point(data: f64, timestamp: f64, iteration: u64, measurement: adc::Measurement) -> DataPoint {
+    let point = generate_data_point(data, timestamp, iteration, measurement);
+
+    DataPoint { channel: RAW_CHANNEL_BASE + point.channel, ..point }
+}
+
+/// Data points for the health of the ADC at `index` in the board definition:
+/// AVDD, DVDD and reference as rail voltages, then die temperature in kelvin
+/// like the thermocouples.
+pub fn generate_health_points(index: usize, health: &adc::Health, timestamp: f64) -> [DataPoint; 4] {
+    let base = HEALTH_CHANNEL_BASE + 4 * index as u32;
+    let point = |offset: u32, value: f64, channel_type: ChannelType| DataPoint {
+        value,
+        timestamp,
+        channel: base + offset,
+        channel_type,
+    };
+
+    [
+        point(0, health.avdd, ChannelType::RailVoltage),
+        point(1, health.dvdd, ChannelType::RailVoltage),
+        point(2, health.reference, ChannelType::RailVoltage),
+        point(3, health.temperature + 273.15, ChannelType::Tc),
+    ]
+}
+
 fn iteration_to_node_id(measurement: adc::Measurement, iteration: u64) -> Option<u32> {
     match measurement {
         adc::Measurement::CurrentLoopPt | adc::Measurement::IValve | adc::Measurement::VValve => {
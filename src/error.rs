@@ -0,0 +1,127 @@
+use std::{error::Error, fmt, io, time::Duration};
+
+use crate::adc::{registers::Part, Measurement};
+
+#[derive(Debug)]
+pub enum SamError {
+    /// The requested GPIO controller doesn't exist on the AM335x.
+    InvalidController(usize),
+    /// `/dev/mem` couldn't be opened, usually for lack of root.
+    MemoryDevice(io::Error),
+    /// A GPIO controller's registers couldn't be mapped.
+    GpioMap { controller: usize, source: io::Error },
+    /// A GPIO controller's character device couldn't be opened.
+    GpioChip { controller: usize, source: gpio_cdev::Error },
+    /// A line of a GPIO character device couldn't be requested, read or
+    /// written.
+    GpioLine { controller: usize, line: usize, source: gpio_cdev::Error },
+    /// The output lines of a GPIO character device couldn't be requested or
+    /// written together.
+    GpioLines { controller: usize, lines: Vec<usize>, source: gpio_cdev::Error },
+    /// The SPI device couldn't be opened or configured.
+    Spi { path: String, source: io::Error },
+    /// An ADC never asserted DRDY.
+    DataReadyTimeout { measurement: Measurement, timeout: Duration },
+    /// Waiting on an ADC's DRDY line failed.
+    DataReady { measurement: Measurement, source: io::Error },
+    /// An SPI transfer to an ADC failed.
+    AdcTransfer { measurement: Measurement, source: io::Error },
+    /// An ADC register doesn't read back what was written to it.
+    AdcRegister { measurement: Measurement, register: u8, expected: u8, actual: u8 },
+    /// Conversion data from an ADC failed its CRC.
+    AdcCrc { measurement: Measurement, expected: u8, actual: u8 },
+    /// An ADC flagged a conversion as unusable in its STATUS byte.
+    AdcStatus { measurement: Measurement, status: u8 },
+    /// An ADC reset itself, e.g. on a brownout, and lost its configuration.
+    AdcReset { measurement: Measurement },
+    /// An ADC's ID register names a different part than the board says.
+    AdcPart { measurement: Measurement, part: Part, id: u8 },
+    /// An ADC doesn't have the requested channel, or it can't be calibrated.
+    NoChannel { measurement: Measurement, channel: usize },
+    /// A calibration record, request or transfer file couldn't be read or written.
+    Calibration { path: String, source: io::Error },
+    /// A calibration record, request or transfer file isn't valid.
+    CalibrationParse { path: String, source: toml::de::Error },
+    /// A channel's transfer function can't be applied.
+    InvalidTransfer(String),
+    /// A UDP socket couldn't be bound or configured.
+    Socket { port: u16, source: io::Error },
+    /// The board definition file couldn't be read.
+    BoardRead { path: String, source: io::Error },
+    /// The board definition isn't valid TOML or doesn't match the schema.
+    BoardParse(toml::de::Error),
+    /// The board definition has conflicting or out of range entries.
+    InvalidBoard(String),
+    /// A GPIO used by the board isn't broken out to the P8/P9 headers.
+    NoHeaderPin { controller: usize, bit: usize },
+    /// A header pin's pinmux state couldn't be read or written.
+    Pinmux { pin: String, source: io::Error },
+    /// A header pin is routed to the wrong peripheral.
+    Mismuxed { pin: String, expected: &'static str, actual: String },
+}
+
+impl fmt::Display for SamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SamError::InvalidController(index) => write!(f, "GPIO controller {index} does not exist"),
+            SamError::MemoryDevice(source) => write!(f, "cannot open /dev/mem: {source}"),
+            SamError::GpioMap { controller, source } => write!(f, "cannot map GPIO controller {controller}: {source}"),
+            SamError::GpioChip { controller, source } => write!(f, "cannot open /dev/gpiochip{controller}: {source}"),
+            SamError::GpioLine { controller, line, source } => write!(f, "cannot use line {line} of /dev/gpiochip{controller}: {source}"),
+            SamError::GpioLines { controller, lines, source } => write!(f, "cannot use lines {lines:?} of /dev/gpiochip{controller}: {source}"),
+            SamError::Spi { path, source } => write!(f, "cannot set up SPI device {path}: {source}"),
+            SamError::DataReadyTimeout { measurement, timeout } => write!(f, "{measurement:?} ADC did not assert DRDY within {timeout:?}"),
+            SamError::DataReady { measurement, source } => write!(f, "cannot wait on {measurement:?} ADC DRDY: {source}"),
+            SamError::AdcTransfer { measurement, source } => write!(f, "SPI transfer to {measurement:?} ADC failed: {source}"),
+            SamError::AdcRegister { measurement, register, expected, actual } => write!(f, "{measurement:?} ADC register {register:#04x} reads back {actual:#04x}, expected {expected:#04x}"),
+            SamError::AdcCrc { measurement, expected, actual } => write!(f, "{measurement:?} ADC conversion CRC is {actual:#04x}, expected {expected:#04x}"),
+            SamError::AdcStatus { measurement, status } => write!(f, "{measurement:?} ADC flagged conversion with status {status:#04x}"),
+            SamError::AdcReset { measurement } => write!(f, "{measurement:?} ADC reset itself since it was configured"),
+            SamError::AdcPart { measurement, part, id } => write!(f, "{measurement:?} ADC has ID {id:#04x}, which is not an {part:?}"),
+            SamError::NoChannel { measurement, channel } => write!(f, "{measurement:?} ADC has no calibratable channel {channel}"),
+            SamError::Calibration { path, source } => write!(f, "cannot access calibration file {path}: {source}"),
+            SamError::CalibrationParse { path, source } => write!(f, "cannot parse calibration file {path}: {source}"),
+            SamError::InvalidTransfer(message) => write!(f, "invalid transfer function: {message}"),
+            SamError::Socket { port, source } => write!(f, "cannot set up socket on port {port}: {source}"),
+            SamError::BoardRead { path, source } => write!(f, "cannot read board definition {path}: {source}"),
+            SamError::BoardParse(source) => write!(f, "cannot parse board definition: {source}"),
+            SamError::InvalidBoard(message) => write!(f, "invalid board definition: {message}"),
+            SamError::NoHeaderPin { controller, bit } => write!(f, "GPIO {controller}/{bit} is not on the P8/P9 headers"),
+            SamError::Pinmux { pin, source } => write!(f, "cannot access pinmux state of {pin}: {source}"),
+            SamError::Mismuxed { pin, expected, actual } => write!(f, "{pin} is muxed as {actual}, expected {expected}"),
+        }
+    }
+}
+
+impl Error for SamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SamError::InvalidController(_)
+            | SamError::DataReadyTimeout { .. }
+            | SamError::AdcRegister { .. }
+            | SamError::NoChannel { .. }
+            | SamError::AdcCrc { .. }
+            | SamError::AdcStatus { .. }
+            | SamError::AdcReset { .. }
+            | SamError::AdcPart { .. }
+            | SamError::InvalidTransfer(_)
+            | SamError::InvalidBoard(_)
+            | SamError::NoHeaderPin { .. }
+            | SamError::Mismuxed { .. } => None,
+            SamError::MemoryDevice(source) => Some(source),
+            SamError::GpioMap { source, .. } => Some(source),
+            SamError::GpioChip { source, .. } => Some(source),
+            SamError::GpioLine { source, .. } => Some(source),
+            SamError::GpioLines { source, .. } => Some(source),
+            SamError::Spi { source, .. } => Some(source),
+            SamError::DataReady { source, .. } => Some(source),
+            SamError::AdcTransfer { source, .. } => Some(source),
+            SamError::Calibration { source, .. } => Some(source),
+            SamError::CalibrationParse { source, .. } => Some(source),
+            SamError::Socket { source, .. } => Some(source),
+            SamError::BoardRead { source, .. } => Some(source),
+            SamError::BoardParse(source) => Some(source),
+            SamError::Pinmux { source, .. } => Some(source),
+        }
+    }
+}
@@ -13,23 +13,26 @@
  * Table 12 and 13 were used to determine the P[8/9]_pin_number on expansion header -> gpio controller value in chip
  */
 
-use libc::{c_int, c_void, off_t, size_t};
-use std::{ffi::CString, sync::{Arc, Mutex}};
+mod chardev;
+mod registers;
 
-const GPIO_BASE_REGISTERS: [off_t; 4] = [0x44E0_7000, 0x4804_C000, 0x481A_C000, 0x481A_E000];
-const GPIO_REGISTER_SIZE: size_t = 0xFFF;
+pub use chardev::ChardevGpio;
+pub use registers::{GpioRegisters, Register};
 
-const GPIO_OE_REGISTER: isize = 0x134;
-const GPIO_DATAOUT_REGISTER: isize = 0x13C;
-const GPIO_DATAIN_REGISTER: isize = 0x138;
+use crate::error::SamError;
 
-#[derive(Debug, PartialEq)]
+use std::{io, sync::{Arc, Mutex, OnceLock}, thread, time::{Duration, Instant}};
+
+// how often backends without edge events re-check a pin while waiting on it
+const WAIT_POLL_INTERVAL: Duration = Duration::from_micros(50);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PinValue {
     Low = 0,
     High = 1,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum PinMode {
     Output,
     Input,
@@ -41,119 +44,478 @@ pub enum BitOrder {
     MSBFirst,
 }
 
-pub struct Gpio {
-    fd: c_int,
-    base: Mutex<*mut c_void>,
-    oe: Mutex<*mut u32>,
-    dataout: Mutex<*mut u32>,
-    datain: *const u32,
+/// Register-level operations a GPIO controller must support. `index` is the
+/// bit (0-31) of the pin within the controller.
+pub trait GpioBackend: Send + Sync {
+    /// Makes every pin in `outputs` an output and every pin in `inputs` an
+    /// input, leaving the direction of all other pins untouched.
+    fn mode_mask(&self, outputs: u32, inputs: u32) -> Result<(), SamError>;
+
+    /// Drives every pin in `high` high and every pin in `low` low, leaving
+    /// all other outputs untouched.
+    fn write_mask(&self, high: u32, low: u32) -> Result<(), SamError>;
+
+    fn digital_read(&self, index: usize) -> Result<PinValue, SamError>;
+
+    fn mode(&self, index: usize, mode: PinMode) -> Result<(), SamError> {
+        match mode {
+            PinMode::Output => self.mode_mask(1 << index, 0),
+            PinMode::Input => self.mode_mask(0, 1 << index),
+        }
+    }
+
+    fn digital_write(&self, index: usize, value: PinValue) -> Result<(), SamError> {
+        match value {
+            PinValue::Low => self.write_mask(0, 1 << index),
+            PinValue::High => self.write_mask(1 << index, 0),
+        }
+    }
+
+    /// Blocks until the input at `index` reads low or `timeout` passes,
+    /// returning whether it went low. Backends that can't be woken by the pin
+    /// fall back to sleeping between reads.
+    fn wait_for_low(&self, index: usize, timeout: Duration) -> io::Result<bool> {
+        sleep_until_low(self, index, timeout)
+    }
+}
+
+fn sleep_until_low<B: GpioBackend + ?Sized>(backend: &B, index: usize, timeout: Duration) -> io::Result<bool> {
+    let start = Instant::now();
+
+    loop {
+        if backend.digital_read(index).map_err(io::Error::other)? == PinValue::Low {
+            return Ok(true);
+        }
+
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
 }
 
-unsafe impl Send for Gpio {}
-unsafe impl Sync for Gpio {}
+pub struct Gpio {
+    backend: Box<dyn GpioBackend>,
+}
 
 pub struct Pin {
     gpio: Arc<Gpio>,
     index: usize,
 }
 
-impl Drop for Gpio {
-    fn drop(&mut self) {
-        unsafe {
-            libc::munmap(*self.base.lock().unwrap(), GPIO_REGISTER_SIZE);
-            libc::close(self.fd);
-        };
-    }
+/// Pins from any number of controllers that change direction and value
+/// together. Each operation is a single masked write per controller, so pins
+/// sharing a bank switch at the same instant.
+pub struct PinGroup {
+    banks: Vec<(Arc<Gpio>, u32)>,
 }
 
 impl Gpio {
-    pub fn open(index: usize) -> Arc<Gpio> {
-        let path = CString::new("/dev/mem").unwrap();
-        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR) };
+    /// Maps the controller at `index` out of `/dev/mem`.
+    pub fn open(index: usize) -> Result<Arc<Gpio>, SamError> {
+        Ok(Gpio::new(Box::new(MemoryMappedGpio::open(index)?)))
+    }
+
+    /// Requests pins of the controller at `index` through the kernel's GPIO
+    /// character device rather than mapping `/dev/mem`.
+    pub fn open_chardev(index: usize) -> Result<Arc<Gpio>, SamError> {
+        Ok(Gpio::new(Box::new(ChardevGpio::open(index)?)))
+    }
+
+    /// Creates a controller backed by an in-memory register file instead of
+    /// the hardware.
+    pub fn in_memory() -> Arc<Gpio> {
+        Gpio::new(Box::new(RegisterFileGpio::new()))
+    }
+
+    pub fn new(backend: Box<dyn GpioBackend>) -> Arc<Gpio> {
+        Arc::new(Gpio { backend })
+    }
+
+    pub fn get_pin(self: &Arc<Self>, index: usize) -> Pin {
+        Pin { gpio: self.clone(), index }
+    }
+
+    /// Sets several outputs of this controller at once. Bits in `high` are
+    /// driven high, bits in `low` are driven low, and every other pin keeps
+    /// its current value.
+    pub fn write_mask(&self, high: u32, low: u32) -> Result<(), SamError> {
+        self.backend.write_mask(high, low)
+    }
+}
+
+impl Pin {
+    pub fn mode(&self, mode: PinMode) -> Result<(), SamError> {
+        self.gpio.backend.mode(self.index, mode)
+    }
+
+    pub fn digital_write(&self, value: PinValue) -> Result<(), SamError> {
+        self.gpio.backend.digital_write(self.index, value)
+    }
+
+    pub fn digital_read(&self) -> Result<PinValue, SamError> {
+        self.gpio.backend.digital_read(self.index)
+    }
+
+    /// Blocks until this pin reads low or `timeout` passes, returning whether
+    /// it went low in time.
+    pub fn wait_for_low(&self, timeout: Duration) -> io::Result<bool> {
+        self.gpio.backend.wait_for_low(self.index, timeout)
+    }
+
+    /// Bit of this pin within its controller, for use with `Gpio::write_mask`.
+    pub fn mask(&self) -> u32 {
+        1 << self.index
+    }
+}
+
+impl PinGroup {
+    pub fn new<'a>(pins: impl IntoIterator<Item = &'a Pin>) -> PinGroup {
+        let mut banks: Vec<(Arc<Gpio>, u32)> = Vec::new();
 
-        if fd < 0 {
-            panic!("Cannot open memory device");
+        for pin in pins {
+            match banks.iter_mut().find(|(gpio, _)| Arc::ptr_eq(gpio, &pin.gpio)) {
+                Some((_, mask)) => *mask |= pin.mask(),
+                None => banks.push((pin.gpio.clone(), pin.mask())),
+            }
         }
 
-        let base = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                GPIO_REGISTER_SIZE,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED,
-                fd,
-                GPIO_BASE_REGISTERS[index],
-            )
-        };
+        PinGroup { banks }
+    }
+
+    /// Every bank is set even if an earlier one fails, and the first failure
+    /// is returned.
+    pub fn mode(&self, mode: PinMode) -> Result<(), SamError> {
+        let mut result = Ok(());
+
+        for (gpio, mask) in &self.banks {
+            let set = match mode {
+                PinMode::Output => gpio.backend.mode_mask(*mask, 0),
+                PinMode::Input => gpio.backend.mode_mask(0, *mask),
+            };
+
+            result = result.and(set);
+        }
+
+        result
+    }
 
-        if base.is_null() {
-            panic!("Cannot map GPIO");
+    /// Every bank is written even if an earlier one fails, and the first
+    /// failure is returned.
+    pub fn digital_write(&self, value: PinValue) -> Result<(), SamError> {
+        let mut result = Ok(());
+
+        for (gpio, mask) in &self.banks {
+            let written = match value {
+                PinValue::Low => gpio.write_mask(0, *mask),
+                PinValue::High => gpio.write_mask(*mask, 0),
+            };
+
+            result = result.and(written);
         }
-        // } else if base != GPIO_BASE_REGISTERS[index] as *mut c_void {
-        //     panic!("Cannot acquire GPIO at {index}. Did you call Gpio::open twice?");
-        // }
-
-        let oe = Mutex::new(unsafe {
-            base.offset(GPIO_OE_REGISTER) as *mut u32
-        });
-        
-        let dataout = Mutex::new(unsafe {
-            base.offset(GPIO_DATAOUT_REGISTER) as *mut u32
-        });
-
-        let datain = unsafe {
-            base.offset(GPIO_DATAIN_REGISTER) as *mut u32
+
+        result
+    }
+}
+
+/// Drives `high` pins high and `low` pins low with one masked write per
+/// controller, e.g. to hand the SPI bus from one chip select to another.
+pub fn write_pins(high: &[&Pin], low: &[&Pin]) -> Result<(), SamError> {
+    let mut banks: Vec<(&Arc<Gpio>, u32, u32)> = Vec::new();
+
+    for (pin, value) in high.iter().map(|pin| (pin, PinValue::High)).chain(low.iter().map(|pin| (pin, PinValue::Low))) {
+        let position = match banks.iter().position(|(gpio, ..)| Arc::ptr_eq(gpio, &pin.gpio)) {
+            Some(position) => position,
+            None => {
+                banks.push((&pin.gpio, 0, 0));
+                banks.len() - 1
+            }
         };
 
-        let base = Mutex::new(base);
+        match value {
+            PinValue::High => banks[position].1 |= pin.mask(),
+            PinValue::Low => banks[position].2 |= pin.mask(),
+        }
+    }
+
+    banks.into_iter().try_for_each(|(gpio, high, low)| gpio.write_mask(high, low))
+}
 
-        Arc::new(Gpio {
-            fd,
-            base,
-            oe,
-            dataout,
-            datain,
+pub struct MemoryMappedGpio {
+    index: usize,
+    events: OnceLock<Option<ChardevGpio>>,
+    registers: GpioRegisters,
+    // held across read-modify-writes of registers without set/clear aliases
+    modify: Mutex<()>,
+}
+
+impl MemoryMappedGpio {
+    pub fn open(index: usize) -> Result<MemoryMappedGpio, SamError> {
+        Ok(MemoryMappedGpio {
+            index,
+            events: OnceLock::new(),
+            registers: GpioRegisters::map(index)?,
+            modify: Mutex::new(()),
         })
     }
 
-    pub fn get_pin(self: &Arc<Self>, index: usize) -> Pin {
-        return Pin { gpio: self.clone(), index };
+    pub fn registers(&self) -> &GpioRegisters {
+        &self.registers
+    }
+
+    /// Clears the `clear` bits and sets the `set` bits of a register under
+    /// the controller's lock.
+    pub fn modify(&self, register: Register, set: u32, clear: u32) {
+        let _guard = self.modify.lock().unwrap();
+        let bits = self.registers.read(register);
+
+        self.registers.write(register, (bits & !clear) | set);
     }
 }
 
-impl Pin {
-    pub fn mode(&self, mode: PinMode) {
-        let oe = self.gpio.oe.lock().unwrap();
-        let mut bits = unsafe { std::ptr::read_volatile(*oe) };
+impl GpioBackend for MemoryMappedGpio {
+    // OE has no set/clear counterpart, so direction changes stay a locked
+    // read-modify-write
+    fn mode_mask(&self, outputs: u32, inputs: u32) -> Result<(), SamError> {
+        self.modify(Register::Oe, inputs, outputs);
+        Ok(())
+    }
 
-        bits = match mode {
-            PinMode::Output => bits & !(1 << self.index),
-            PinMode::Input => bits | (1 << self.index),
-        };
+    // SETDATAOUT and CLEARDATAOUT only act on the bits written as 1, so the
+    // controller updates DATAOUT atomically and no lock is needed, even
+    // against other processes writing the same bank
+    fn write_mask(&self, high: u32, low: u32) -> Result<(), SamError> {
+        if high != 0 {
+            self.registers.write(Register::SetDataOut, high);
+        }
 
-        unsafe { std::ptr::write_volatile(*oe, bits) };
+        if low != 0 {
+            self.registers.write(Register::ClearDataOut, low);
+        }
+
+        Ok(())
     }
 
-    pub fn digital_write(&self, value: PinValue) {
-        let dataout = self.gpio.dataout.lock().unwrap();
-        let mut bits = unsafe { std::ptr::read_volatile(*dataout) };
+    fn digital_read(&self, index: usize) -> Result<PinValue, SamError> {
+        let bits = self.registers.read(Register::DataIn);
 
-        bits = match value {
-            PinValue::Low => bits & !(1 << self.index),
-            PinValue::High => bits | (1 << self.index),
-        };
+        if bits & (1 << index) != 0 {
+            Ok(PinValue::High)
+        } else {
+            Ok(PinValue::Low)
+        }
+    }
+
+    // the mapped registers can't block on an edge, so waits go through the
+    // same controller's GPIO character device when the kernel provides one
+    fn wait_for_low(&self, index: usize, timeout: Duration) -> io::Result<bool> {
+        let events = self.events.get_or_init(|| ChardevGpio::open(self.index).ok());
+
+        match events {
+            Some(events) => events.wait_for_low(index, timeout),
+            None => sleep_until_low(self, index, timeout),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Registers {
+    oe: u32,
+    dataout: u32,
+    datain: u32,
+}
+
+/// Software stand-in for a GPIO controller which keeps OE, DATAOUT and
+/// DATAIN in memory. Clones share the same registers, so a handle can be kept
+/// around to inspect pin state after the backend has been handed to a `Gpio`.
+#[derive(Clone)]
+pub struct RegisterFileGpio {
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl RegisterFileGpio {
+    pub fn new() -> RegisterFileGpio {
+        // matches the AM335x reset state: every pin an input, outputs low
+        let registers = Registers { oe: 0xFFFF_FFFF, dataout: 0, datain: 0 };
+
+        RegisterFileGpio { registers: Arc::new(Mutex::new(registers)) }
+    }
 
-        unsafe { std::ptr::write_volatile(*dataout, bits) };
+    pub fn oe(&self) -> u32 {
+        self.registers.lock().unwrap().oe
     }
 
-    pub fn digital_read(&self) -> PinValue {
-        let datain = self.gpio.datain;
-        let bits = unsafe { std::ptr::read_volatile(datain) };
+    pub fn dataout(&self) -> u32 {
+        self.registers.lock().unwrap().dataout
+    }
 
-        if bits & (1 << self.index) != 0 {
+    pub fn is_output(&self, index: usize) -> bool {
+        self.oe() & (1 << index) == 0
+    }
+
+    pub fn output_value(&self, index: usize) -> PinValue {
+        if self.dataout() & (1 << index) != 0 {
             PinValue::High
         } else {
             PinValue::Low
         }
     }
-}
\ No newline at end of file
+
+    /// Drives the level seen by `digital_read` on an input pin.
+    pub fn set_input(&self, index: usize, value: PinValue) {
+        let mut registers = self.registers.lock().unwrap();
+
+        registers.datain = match value {
+            PinValue::Low => registers.datain & !(1 << index),
+            PinValue::High => registers.datain | (1 << index),
+        };
+    }
+}
+
+impl Default for RegisterFileGpio {
+    fn default() -> RegisterFileGpio {
+        RegisterFileGpio::new()
+    }
+}
+
+impl GpioBackend for RegisterFileGpio {
+    fn mode_mask(&self, outputs: u32, inputs: u32) -> Result<(), SamError> {
+        let mut registers = self.registers.lock().unwrap();
+
+        registers.oe = (registers.oe & !outputs) | inputs;
+        Ok(())
+    }
+
+    fn write_mask(&self, high: u32, low: u32) -> Result<(), SamError> {
+        let mut registers = self.registers.lock().unwrap();
+
+        registers.dataout = (registers.dataout | high) & !low;
+        Ok(())
+    }
+
+    fn digital_read(&self, index: usize) -> Result<PinValue, SamError> {
+        let registers = self.registers.lock().unwrap();
+
+        // output pins read back what they are driving, like the real DATAIN
+        let bits = (registers.dataout & !registers.oe) | (registers.datain & registers.oe);
+
+        if bits & (1 << index) != 0 {
+            Ok(PinValue::High)
+        } else {
+            Ok(PinValue::Low)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> (RegisterFileGpio, Arc<Gpio>) {
+        let registers = RegisterFileGpio::new();
+        let gpio = Gpio::new(Box::new(registers.clone()));
+
+        (registers, gpio)
+    }
+
+    #[test]
+    fn pins_start_as_inputs_driving_low() {
+        let (registers, _gpio) = controller();
+
+        assert_eq!(registers.oe(), 0xFFFF_FFFF);
+        assert_eq!(registers.dataout(), 0);
+    }
+
+    #[test]
+    fn output_clears_its_oe_bit_only() {
+        let (registers, gpio) = controller();
+        let pin = gpio.get_pin(5);
+
+        pin.mode(PinMode::Output).unwrap();
+        assert_eq!(registers.oe(), !(1 << 5));
+        assert!(registers.is_output(5));
+
+        pin.mode(PinMode::Input).unwrap();
+        assert_eq!(registers.oe(), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn outputs_read_back_what_they_drive() {
+        let (registers, gpio) = controller();
+        let pin = gpio.get_pin(12);
+
+        pin.mode(PinMode::Output).unwrap();
+        pin.digital_write(PinValue::High).unwrap();
+        assert_eq!(registers.output_value(12), PinValue::High);
+        assert_eq!(pin.digital_read().unwrap(), PinValue::High);
+
+        // the level on the wire is ignored while the pin drives it
+        registers.set_input(12, PinValue::Low);
+        assert_eq!(pin.digital_read().unwrap(), PinValue::High);
+
+        pin.digital_write(PinValue::Low).unwrap();
+        assert_eq!(pin.digital_read().unwrap(), PinValue::Low);
+    }
+
+    #[test]
+    fn inputs_read_datain() {
+        let (registers, gpio) = controller();
+        let pin = gpio.get_pin(3);
+
+        registers.set_input(3, PinValue::High);
+        assert_eq!(pin.digital_read().unwrap(), PinValue::High);
+
+        registers.set_input(3, PinValue::Low);
+        assert_eq!(pin.digital_read().unwrap(), PinValue::Low);
+    }
+
+    #[test]
+    fn write_mask_leaves_other_outputs_alone() {
+        let (registers, gpio) = controller();
+
+        gpio.write_mask(0b1010, 0).unwrap();
+        gpio.write_mask(0b0001, 0b0010).unwrap();
+        assert_eq!(registers.dataout(), 0b1001);
+
+        // like SETDATAOUT and CLEARDATAOUT, zero bits are no-ops
+        gpio.write_mask(0, 0).unwrap();
+        assert_eq!(registers.dataout(), 0b1001);
+    }
+
+    #[test]
+    fn pin_group_spans_controllers() {
+        let (first, gpio0) = controller();
+        let (second, gpio1) = controller();
+        let pins = [gpio0.get_pin(1), gpio0.get_pin(4), gpio1.get_pin(7)];
+        let group = PinGroup::new(&pins);
+
+        group.mode(PinMode::Output).unwrap();
+        assert_eq!(first.oe(), !0b10010);
+        assert_eq!(second.oe(), !(1 << 7));
+
+        gpio0.get_pin(9).mode(PinMode::Output).unwrap();
+        gpio0.get_pin(9).digital_write(PinValue::High).unwrap();
+
+        group.digital_write(PinValue::High).unwrap();
+        assert_eq!(first.dataout(), 0b10_0001_0010);
+        assert_eq!(second.dataout(), 1 << 7);
+
+        group.digital_write(PinValue::Low).unwrap();
+        assert_eq!(first.dataout(), 1 << 9);
+        assert_eq!(second.dataout(), 0);
+    }
+
+    #[test]
+    fn write_pins_hands_over_in_one_write() {
+        let (registers, gpio) = controller();
+        let old = gpio.get_pin(2);
+        let new = gpio.get_pin(6);
+
+        new.digital_write(PinValue::High).unwrap();
+        write_pins(&[&new], &[&old]).unwrap();
+        write_pins(&[&old], &[&new]).unwrap();
+        assert_eq!(registers.output_value(2), PinValue::High);
+        assert_eq!(registers.output_value(6), PinValue::Low);
+    }
+}
@@ -0,0 +1,373 @@
+//! GPIO backend built on the kernel's GPIO character device.
+//!
+//! https://www.kernel.org/doc/html/latest/userspace-api/gpio/chardev_v1.html
+//! Instead of mapping the controller registers out of /dev/mem, each pin is
+//! requested as a line from /dev/gpiochipN. The kernel holds the line for us
+//! until the handle is dropped, so two processes can't drive the same pin and
+//! SAM doesn't need raw memory access. Controller N is expected at
+//! /dev/gpiochipN, which holds on the BeagleBone kernels we run.
+//!
+//! Line direction is fixed when a line is requested, so changing a pin's mode
+//! re-requests the line. Every output line of a controller is held in one
+//! request, so a masked write sets them all with a single ioctl and pins
+//! sharing a bank switch together, as with SETDATAOUT/CLEARDATAOUT on the
+//! real controller. Changing which lines are outputs re-requests all of them,
+//! so modes are best set once at startup. Writes to a pin that isn't an
+//! output are latched and applied once it becomes one, like DATAOUT.
+//!
+//! Waiting on a pin requests it with falling-edge events, which lets the
+//! kernel wake us from the GPIO interrupt instead of spinning on the level.
+//!
+//! Off the BeagleBone, the gpio-sim or gpio-mockup kernel module can stand in
+//! for the controllers, e.g.
+//!
+//!     modprobe gpio-mockup gpio_mockup_ranges=-1,32,-1,32,-1,32,-1,32
+
+use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineHandle, LineRequestFlags, MultiLineHandle};
+use std::{collections::HashMap, io, os::fd::AsRawFd, sync::Mutex, time::{Duration, Instant}};
+
+use super::{GpioBackend, PinMode, PinValue};
+use crate::error::SamError;
+
+const CONSUMER: &str = "sam";
+
+struct Line {
+    // only held for inputs, outputs are held together in Bank::outputs
+    handle: Option<LineHandle>,
+    events: Option<LineEventHandle>,
+    mode: Option<PinMode>,
+    value: PinValue,
+}
+
+impl Line {
+    fn new() -> Line {
+        Line { handle: None, events: None, mode: None, value: PinValue::Low }
+    }
+}
+
+/// Every output line of the controller in one request, with the mask of the
+/// lines it holds.
+struct Outputs {
+    mask: u32,
+    handle: MultiLineHandle,
+}
+
+struct Bank {
+    lines: HashMap<usize, Line>,
+    outputs: Option<Outputs>,
+}
+
+impl Bank {
+    // the lines that should be held as outputs
+    fn output_mask(&self) -> u32 {
+        self.lines
+            .iter()
+            .filter(|(_, line)| line.mode == Some(PinMode::Output))
+            .fold(0, |mask, (index, _)| mask | (1 << index))
+    }
+
+    // latched values of the lines in `mask`, lowest line first
+    fn values(&self, mask: u32) -> Vec<u8> {
+        bits(mask).map(|index| self.lines.get(&index).map_or(0, |line| line.value as u8)).collect()
+    }
+}
+
+fn bits(mask: u32) -> impl Iterator<Item = usize> {
+    (0..32).filter(move |bit| mask & (1 << bit) != 0)
+}
+
+// where a line sits among the values of a request holding `mask`
+fn position(mask: u32, index: usize) -> usize {
+    (mask & ((1 << index) - 1)).count_ones() as usize
+}
+
+pub struct ChardevGpio {
+    index: usize,
+    chip: Mutex<Chip>,
+    bank: Mutex<Bank>,
+}
+
+impl ChardevGpio {
+    pub fn open(index: usize) -> Result<ChardevGpio, SamError> {
+        let chip = Chip::new(format!("/dev/gpiochip{index}"))
+            .map_err(|source| SamError::GpioChip { controller: index, source })?;
+
+        Ok(ChardevGpio {
+            index,
+            chip: Mutex::new(chip),
+            bank: Mutex::new(Bank { lines: HashMap::new(), outputs: None }),
+        })
+    }
+
+    fn line_error(&self, line: usize) -> impl FnOnce(gpio_cdev::Error) -> SamError {
+        let controller = self.index;
+        move |source| SamError::GpioLine { controller, line, source }
+    }
+
+    fn lines_error(&self, mask: u32) -> impl FnOnce(gpio_cdev::Error) -> SamError {
+        let controller = self.index;
+        move |source| SamError::GpioLines { controller, lines: bits(mask).collect(), source }
+    }
+
+    fn request_input(&self, index: usize) -> Result<LineHandle, SamError> {
+        let mut chip = self.chip.lock().unwrap();
+
+        chip.get_line(index as u32)
+            .and_then(|line| line.request(LineRequestFlags::INPUT, 0, CONSUMER))
+            .map_err(self.line_error(index))
+    }
+
+    // Re-requests the outputs when the lines held differ from the lines that
+    // should be. The old request is released first, the kernel won't hand out
+    // a line twice, and a failed request leaves every output released so the
+    // next mode change tries again.
+    fn sync_outputs(&self, bank: &mut Bank) -> Result<(), SamError> {
+        let mask = bank.output_mask();
+
+        if bank.outputs.as_ref().map(|outputs| outputs.mask) == Some(mask) {
+            return Ok(());
+        }
+
+        bank.outputs = None;
+
+        if mask == 0 {
+            return Ok(());
+        }
+
+        let offsets: Vec<u32> = bits(mask).map(|index| index as u32).collect();
+        let handle = self.chip
+            .lock()
+            .unwrap()
+            .get_lines(&offsets)
+            .and_then(|lines| lines.request(LineRequestFlags::OUTPUT, &bank.values(mask), CONSUMER))
+            .map_err(self.lines_error(mask))?;
+
+        bank.outputs = Some(Outputs { mask, handle });
+        Ok(())
+    }
+
+    fn request_events(&self, index: usize) -> io::Result<LineEventHandle> {
+        let mut chip = self.chip.lock().unwrap();
+
+        chip.get_line(index as u32)
+            .and_then(|line| line.events(LineRequestFlags::INPUT, EventRequestFlags::FALLING_EDGE, CONSUMER))
+            .map_err(io::Error::other)
+    }
+}
+
+impl GpioBackend for ChardevGpio {
+    fn mode_mask(&self, outputs: u32, inputs: u32) -> Result<(), SamError> {
+        let mut bank = self.bank.lock().unwrap();
+
+        for index in bits(outputs | inputs) {
+            let line = bank.lines.entry(index).or_insert_with(Line::new);
+            let mode = if inputs & (1 << index) != 0 { PinMode::Input } else { PinMode::Output };
+
+            // lines becoming outputs are released before they're requested
+            // with the others
+            if mode == PinMode::Output {
+                line.handle = None;
+                line.events = None;
+            }
+
+            line.mode = Some(mode);
+        }
+
+        // release lines becoming inputs before requesting them on their own
+        self.sync_outputs(&mut bank)?;
+
+        for index in bits(inputs) {
+            let line = bank.lines.get_mut(&index).unwrap();
+
+            if line.handle.is_none() && line.events.is_none() {
+                line.handle = Some(self.request_input(index)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_mask(&self, high: u32, low: u32) -> Result<(), SamError> {
+        let mut bank = self.bank.lock().unwrap();
+
+        for index in bits(high | low) {
+            let line = bank.lines.entry(index).or_insert_with(Line::new);
+            line.value = if low & (1 << index) != 0 { PinValue::Low } else { PinValue::High };
+        }
+
+        match &bank.outputs {
+            Some(outputs) if outputs.mask & (high | low) != 0 => outputs.handle
+                .set_values(&bank.values(outputs.mask))
+                .map_err(self.lines_error(outputs.mask)),
+            _ => Ok(()),
+        }
+    }
+
+    fn digital_read(&self, index: usize) -> Result<PinValue, SamError> {
+        let mut bank = self.bank.lock().unwrap();
+
+        // outputs read back the level they drive, like DATAIN
+        if let Some(outputs) = bank.outputs.as_ref().filter(|outputs| outputs.mask & (1 << index) != 0) {
+            let values = outputs.handle.get_values().map_err(self.lines_error(outputs.mask))?;
+
+            return Ok(if values[position(outputs.mask, index)] == 0 { PinValue::Low } else { PinValue::High });
+        }
+
+        let line = bank.lines.entry(index).or_insert_with(Line::new);
+
+        if line.handle.is_none() && line.events.is_none() {
+            line.handle = Some(self.request_input(index)?);
+            line.mode = Some(PinMode::Input);
+        }
+
+        let value = match (&line.events, &line.handle) {
+            (Some(events), _) => events.get_value(),
+            (None, Some(handle)) => handle.get_value(),
+            (None, None) => unreachable!("line {index} was requested above"),
+        };
+
+        match value.map_err(self.line_error(index))? {
+            0 => Ok(PinValue::Low),
+            _ => Ok(PinValue::High),
+        }
+    }
+
+    fn wait_for_low(&self, index: usize, timeout: Duration) -> io::Result<bool> {
+        // take the event handle out of the map while blocked so writes to
+        // other pins on this controller aren't held up behind the wait
+        let mut events = {
+            let mut bank = self.bank.lock().unwrap();
+            let line = bank.lines.entry(index).or_insert_with(Line::new);
+
+            match line.events.take() {
+                Some(events) => events,
+                None => {
+                    line.handle = None;
+                    line.mode = Some(PinMode::Input);
+                    self.sync_outputs(&mut bank).map_err(io::Error::other)?;
+                    self.request_events(index)?
+                }
+            }
+        };
+
+        let result = wait_on_events(&mut events, timeout);
+
+        let mut bank = self.bank.lock().unwrap();
+        bank.lines.entry(index).or_insert_with(Line::new).events = Some(events);
+
+        result
+    }
+}
+
+fn wait_on_events(events: &mut LineEventHandle, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        // check the level on every pass, since queued edges may be stale
+        if events.get_value().map_err(io::Error::other)? == 0 {
+            return Ok(true);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+
+        let mut fd = libc::pollfd {
+            fd: events.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let timespec = libc::timespec {
+            tv_sec: remaining.as_secs() as libc::time_t,
+            tv_nsec: remaining.subsec_nanos() as libc::c_long,
+        };
+
+        let ready = unsafe { libc::ppoll(&mut fd, 1, &timespec, std::ptr::null()) };
+
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        } else if ready > 0 {
+            events.get_event().map_err(io::Error::other)?;
+        }
+    }
+}
+
+// Requesting lines needs a GPIO chip from the kernel, which gpio-sim or
+// gpio-mockup can provide but only with root to load them, so the line and
+// request paths are left to running on the board. What doesn't touch a chip
+// is tested here.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bank(lines: &[(usize, PinMode, PinValue)]) -> Bank {
+        let lines = lines
+            .iter()
+            .map(|(index, mode, value)| (*index, Line { mode: Some(*mode), value: *value, ..Line::new() }))
+            .collect();
+
+        Bank { lines, outputs: None }
+    }
+
+    #[test]
+    fn bits_lists_lines_lowest_first() {
+        assert_eq!(bits(0).count(), 0);
+        assert_eq!(bits(0b1010_0001).collect::<Vec<_>>(), [0, 5, 7]);
+        assert_eq!(bits(1 << 31).collect::<Vec<_>>(), [31]);
+    }
+
+    #[test]
+    fn lines_sit_at_their_rank_within_a_request() {
+        let mask = (1 << 3) | (1 << 12) | (1 << 31);
+
+        assert_eq!(position(mask, 3), 0);
+        assert_eq!(position(mask, 12), 1);
+        assert_eq!(position(mask, 31), 2);
+
+        for (rank, index) in bits(mask).enumerate() {
+            assert_eq!(position(mask, index), rank);
+        }
+    }
+
+    #[test]
+    fn only_output_lines_are_held_together() {
+        let bank = bank(&[
+            (2, PinMode::Output, PinValue::High),
+            (9, PinMode::Input, PinValue::High),
+            (17, PinMode::Output, PinValue::Low),
+            (30, PinMode::Output, PinValue::High),
+        ]);
+
+        assert_eq!(bank.output_mask(), (1 << 2) | (1 << 17) | (1 << 30));
+    }
+
+    #[test]
+    fn values_follow_the_request_order() {
+        let bank = bank(&[
+            (30, PinMode::Output, PinValue::High),
+            (2, PinMode::Output, PinValue::High),
+            (17, PinMode::Output, PinValue::Low),
+        ]);
+
+        assert_eq!(bank.values(bank.output_mask()), [1, 0, 1]);
+
+        // a line never written to drives low
+        assert_eq!(bank.values((1 << 2) | (1 << 4)), [1, 0]);
+    }
+
+    #[test]
+    fn a_missing_chip_names_its_controller() {
+        match ChardevGpio::open(99) {
+            Err(SamError::GpioChip { controller, .. }) => assert_eq!(controller, 99),
+            Err(error) => panic!("expected a missing chip, got {error}"),
+            Ok(_) => panic!("/dev/gpiochip99 exists"),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Typed access to the memory mapped registers of one AM335x GPIO controller.
+//!
+//! https://www.ti.com/lit/ug/spruh73q/spruh73q.pdf
+//! Section 25.4.1 lists the GPIO register set. Every register is a 32 bit word
+//! at a fixed offset into the controller's page, with one bit per pin.
+//! `GpioRegisters` owns the mapping of that page and only hands out volatile
+//! reads and writes at offsets that lie inside it.
+
+use libc::{c_int, c_void, off_t, size_t};
+use std::{ffi::CString, io};
+
+use crate::error::SamError;
+
+const GPIO_BASE_REGISTERS: [off_t; 4] = [0x44E0_7000, 0x4804_C000, 0x481A_C000, 0x481A_E000];
+const GPIO_REGISTER_SIZE: size_t = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Revision,
+    SysConfig,
+    Eoi,
+    IrqStatusRaw0,
+    IrqStatusRaw1,
+    IrqStatus0,
+    IrqStatus1,
+    IrqStatusSet0,
+    IrqStatusSet1,
+    IrqStatusClear0,
+    IrqStatusClear1,
+    IrqWaken0,
+    IrqWaken1,
+    SysStatus,
+    Ctrl,
+    Oe,
+    DataIn,
+    DataOut,
+    LevelDetect0,
+    LevelDetect1,
+    RisingDetect,
+    FallingDetect,
+    DebounceEnable,
+    DebouncingTime,
+    ClearDataOut,
+    SetDataOut,
+}
+
+impl Register {
+    pub const fn offset(self) -> usize {
+        match self {
+            Register::Revision => 0x000,
+            Register::SysConfig => 0x010,
+            Register::Eoi => 0x020,
+            Register::IrqStatusRaw0 => 0x024,
+            Register::IrqStatusRaw1 => 0x028,
+            Register::IrqStatus0 => 0x02C,
+            Register::IrqStatus1 => 0x030,
+            Register::IrqStatusSet0 => 0x034,
+            Register::IrqStatusSet1 => 0x038,
+            Register::IrqStatusClear0 => 0x03C,
+            Register::IrqStatusClear1 => 0x040,
+            Register::IrqWaken0 => 0x044,
+            Register::IrqWaken1 => 0x048,
+            Register::SysStatus => 0x114,
+            Register::Ctrl => 0x130,
+            Register::Oe => 0x134,
+            Register::DataIn => 0x138,
+            Register::DataOut => 0x13C,
+            Register::LevelDetect0 => 0x140,
+            Register::LevelDetect1 => 0x144,
+            Register::RisingDetect => 0x148,
+            Register::FallingDetect => 0x14C,
+            Register::DebounceEnable => 0x150,
+            Register::DebouncingTime => 0x154,
+            Register::ClearDataOut => 0x190,
+            Register::SetDataOut => 0x194,
+        }
+    }
+}
+
+/// One GPIO controller's register page mapped from /dev/mem.
+pub struct GpioRegisters {
+    fd: c_int,
+    base: *mut c_void,
+    len: usize,
+}
+
+// the mapping is plain device memory that is only touched through volatile
+// word accesses, which the controller handles one at a time, so it is fine
+// to share between threads. Read-modify-writes still need a lock held by the
+// caller.
+unsafe impl Send for GpioRegisters {}
+unsafe impl Sync for GpioRegisters {}
+
+impl Drop for GpioRegisters {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base, self.len);
+            libc::close(self.fd);
+        };
+    }
+}
+
+impl GpioRegisters {
+    pub fn map(controller: usize) -> Result<GpioRegisters, SamError> {
+        let Some(&address) = GPIO_BASE_REGISTERS.get(controller) else {
+            return Err(SamError::InvalidController(controller));
+        };
+
+        let path = CString::new("/dev/mem").unwrap();
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR) };
+
+        if fd < 0 {
+            return Err(SamError::MemoryDevice(io::Error::last_os_error()));
+        }
+
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                GPIO_REGISTER_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                address,
+            )
+        };
+
+        // mmap signals failure with MAP_FAILED, not a null pointer
+        if base == libc::MAP_FAILED {
+            let source = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(SamError::GpioMap { controller, source });
+        }
+
+        Ok(GpioRegisters { fd, base, len: GPIO_REGISTER_SIZE })
+    }
+
+    // every Register lies well inside the page, so these can't fail
+    pub fn read(&self, register: Register) -> u32 {
+        self.read_offset(register.offset()).unwrap()
+    }
+
+    pub fn write(&self, register: Register, value: u32) {
+        self.write_offset(register.offset(), value).unwrap()
+    }
+
+    /// Reads the word at `offset`, or None if it isn't a word aligned offset
+    /// inside the page.
+    pub fn read_offset(&self, offset: usize) -> Option<u32> {
+        let word = self.word(offset)?;
+        Some(unsafe { std::ptr::read_volatile(word) })
+    }
+
+    /// Writes the word at `offset`, or returns None without writing if it
+    /// isn't a word aligned offset inside the page.
+    pub fn write_offset(&self, offset: usize, value: u32) -> Option<()> {
+        let word = self.word(offset)?;
+        unsafe { std::ptr::write_volatile(word, value) };
+        Some(())
+    }
+
+    fn word(&self, offset: usize) -> Option<*mut u32> {
+        if !offset.is_multiple_of(4) || offset.checked_add(4)? > self.len {
+            return None;
+        }
+
+        Some(unsafe { (self.base as *mut u8).add(offset) as *mut u32 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTERS: [Register; 26] = [
+        Register::Revision,
+        Register::SysConfig,
+        Register::Eoi,
+        Register::IrqStatusRaw0,
+        Register::IrqStatusRaw1,
+        Register::IrqStatus0,
+        Register::IrqStatus1,
+        Register::IrqStatusSet0,
+        Register::IrqStatusSet1,
+        Register::IrqStatusClear0,
+        Register::IrqStatusClear1,
+        Register::IrqWaken0,
+        Register::IrqWaken1,
+        Register::SysStatus,
+        Register::Ctrl,
+        Register::Oe,
+        Register::DataIn,
+        Register::DataOut,
+        Register::LevelDetect0,
+        Register::LevelDetect1,
+        Register::RisingDetect,
+        Register::FallingDetect,
+        Register::DebounceEnable,
+        Register::DebouncingTime,
+        Register::ClearDataOut,
+        Register::SetDataOut,
+    ];
+
+    // a page of ordinary memory standing in for a controller, since /dev/mem
+    // needs root and the real hardware
+    fn page() -> GpioRegisters {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                GPIO_REGISTER_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(base, libc::MAP_FAILED);
+
+        GpioRegisters { fd: -1, base, len: GPIO_REGISTER_SIZE }
+    }
+
+    #[test]
+    fn every_register_is_an_aligned_word_in_the_page() {
+        let registers = page();
+
+        for register in REGISTERS {
+            assert!(register.offset().is_multiple_of(4), "{register:?}");
+            registers.write(register, register.offset() as u32);
+        }
+
+        for register in REGISTERS {
+            assert_eq!(registers.read(register), register.offset() as u32, "{register:?}");
+        }
+    }
+
+    #[test]
+    fn the_last_word_of_the_page_is_in_bounds() {
+        let registers = page();
+
+        assert_eq!(registers.write_offset(0xFFC, 0xDEAD_BEEF), Some(()));
+        assert_eq!(registers.read_offset(0xFFC), Some(0xDEAD_BEEF));
+    }
+
+    #[test]
+    fn offsets_past_the_page_are_refused() {
+        let registers = page();
+
+        for offset in [0x1000, 0x1004, 0xFFFF_0000, usize::MAX - 3] {
+            assert_eq!(registers.read_offset(offset), None, "{offset:#x}");
+            assert_eq!(registers.write_offset(offset, 1), None, "{offset:#x}");
+        }
+    }
+
+    #[test]
+    fn unaligned_offsets_are_refused() {
+        let registers = page();
+        registers.write(Register::DataOut, 0x1234_5678);
+
+        for offset in [0x001, 0x13D, 0x13E, 0xFFD, 0xFFF] {
+            assert_eq!(registers.read_offset(offset), None, "{offset:#x}");
+            assert_eq!(registers.write_offset(offset, 0), None, "{offset:#x}");
+        }
+
+        // and leave the words around them alone
+        assert_eq!(registers.read(Register::DataOut), 0x1234_5678);
+    }
+
+    #[test]
+    fn only_four_controllers_exist() {
+        assert!(matches!(GpioRegisters::map(4), Err(SamError::InvalidController(4))));
+    }
+}
@@ -1,35 +1,81 @@
 pub mod gpio;
 pub mod adc;
+pub mod ads114s0x;
+pub mod board;
+pub mod calibration;
 pub mod command;
 pub mod data;
 pub mod discovery;
+pub mod error;
+pub mod pinmux;
+pub mod rtd;
+pub mod scan;
+pub mod spi;
 pub mod state;
 pub mod tc;
+pub mod transducer;
+pub mod transfer;
 
-use std::{thread, sync::Arc};
+use std::{thread, sync::{mpsc::{self, Receiver}, Arc}, time::Duration};
 use adc::open_controllers;
-use command::begin;
+use board::Board;
+use calibration::Request;
+use command::{begin, begin_calibration};
 use gpio::Gpio;
+use jeflog::fail;
+
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
 fn main() {
-    let controllers = open_controllers();
+    let board = match Board::load() {
+        Ok(board) => Arc::new(board),
+        Err(error) => {
+            fail!("Could not load board definition: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let controllers = loop {
+        match open_controllers() {
+            Ok(controllers) => break controllers,
+            Err(error) => fail!("Could not open GPIO controllers: {}", error),
+        }
+
+        thread::sleep(RETRY_DELAY);
+    };
     let controllers1 = controllers.clone();
     let controllers2 = controllers.clone();
+    let board1 = board.clone();
+    let board2 = board.clone();
+    let (calibration_sender, calibration_requests) = mpsc::channel();
     
     let state_thread = thread::spawn( move || {
-        init_state(controllers1);
+        init_state(controllers1, board1, calibration_requests);
+    });
+
+    let calibration_thread = thread::spawn( move || {
+        begin_calibration(calibration_sender);
     });
 
     let command_thread = thread::spawn( move || {
-        begin(controllers2.clone());
+        begin(controllers2.clone(), board2);
     });
 
     state_thread.join().expect("Could not join state thread");
     command_thread.join().expect("Could not join command thread");
+    calibration_thread.join().expect("Could not join calibration thread");
 }
 
-fn init_state(controllers: Vec<Arc<Gpio>>) {
+fn init_state(controllers: Vec<Arc<Gpio>>, board: Arc<Board>, calibration_requests: Receiver<Request>) {
     let mut sam_state = state::State::Init;
-    let mut data = state::Data::new(controllers);
+    let mut data = loop {
+        match state::Data::new(controllers.clone(), board.clone()) {
+            Ok(data) => break data.with_calibration_requests(calibration_requests),
+            Err(error) => fail!("Could not set up SAM data: {}", error),
+        }
+
+        thread::sleep(RETRY_DELAY);
+    };
     loop {
         sam_state = sam_state.next(&mut data);
     }
@@ -0,0 +1,248 @@
+//! Pin multiplexing for the BeagleBone header pins SAM uses.
+//!
+//! Every header pin can be routed to one of several peripherals. With the
+//! cape-universal overlay loaded, each pin exposes its current routing at
+//! <root>/ocp:<PIN>_pinmux/state, which is what config-pin reads and writes.
+//! Writing a mode name (gpio, spi, spi_cs, spi_sclk) to that file re-routes
+//! the pin, and reading it back reports the mode the pin is actually in.
+//!
+//! The root defaults to /sys/devices/platform/ocp and can be pointed at any
+//! directory laid out the same way, e.g. one made by `create_fake_tree`.
+
+use std::{fs, io, path::{Path, PathBuf}};
+
+use crate::{board::{Board, BoardPin}, error::SamError};
+
+const DEFAULT_ROOT: &str = "/sys/devices/platform/ocp";
+
+/// (controller, bit, header pin) for every GPIO broken out to P8 and P9.
+/// From Tables 12 and 13 of the BeagleBone Black SRM.
+const HEADER_PINS: [(usize, usize, &str); 65] = [
+    (1, 6, "P8_03"), (1, 7, "P8_04"), (1, 2, "P8_05"), (1, 3, "P8_06"),
+    (2, 2, "P8_07"), (2, 3, "P8_08"), (2, 5, "P8_09"), (2, 4, "P8_10"),
+    (1, 13, "P8_11"), (1, 12, "P8_12"), (0, 23, "P8_13"), (0, 26, "P8_14"),
+    (1, 15, "P8_15"), (1, 14, "P8_16"), (0, 27, "P8_17"), (2, 1, "P8_18"),
+    (0, 22, "P8_19"), (1, 31, "P8_20"), (1, 30, "P8_21"), (1, 5, "P8_22"),
+    (1, 4, "P8_23"), (1, 1, "P8_24"), (1, 0, "P8_25"), (1, 29, "P8_26"),
+    (2, 22, "P8_27"), (2, 24, "P8_28"), (2, 23, "P8_29"), (2, 25, "P8_30"),
+    (0, 10, "P8_31"), (0, 11, "P8_32"), (0, 9, "P8_33"), (2, 17, "P8_34"),
+    (0, 8, "P8_35"), (2, 16, "P8_36"), (2, 14, "P8_37"), (2, 15, "P8_38"),
+    (2, 12, "P8_39"), (2, 13, "P8_40"), (2, 10, "P8_41"), (2, 11, "P8_42"),
+    (2, 8, "P8_43"), (2, 9, "P8_44"), (2, 6, "P8_45"), (2, 7, "P8_46"),
+    (0, 30, "P9_11"), (1, 28, "P9_12"), (0, 31, "P9_13"), (1, 18, "P9_14"),
+    (1, 16, "P9_15"), (1, 19, "P9_16"), (0, 5, "P9_17"), (0, 4, "P9_18"),
+    (0, 13, "P9_19"), (0, 12, "P9_20"), (0, 3, "P9_21"), (0, 2, "P9_22"),
+    (1, 17, "P9_23"), (0, 15, "P9_24"), (3, 21, "P9_25"), (0, 14, "P9_26"),
+    (3, 19, "P9_27"), (3, 17, "P9_28"), (3, 15, "P9_29"), (3, 16, "P9_30"),
+    (3, 14, "P9_31"),
+];
+
+// P9_41 and P9_42 are each wired to two GPIOs, only one of which may be muxed
+const SHARED_HEADER_PINS: [(usize, usize, &str); 4] = [
+    (0, 20, "P9_41"), (3, 20, "P9_41"), (0, 7, "P9_42"), (3, 18, "P9_42"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinmuxMode {
+    Gpio,
+    Spi,
+    SpiCs,
+    SpiSclk,
+}
+
+impl PinmuxMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PinmuxMode::Gpio => "gpio",
+            PinmuxMode::Spi => "spi",
+            PinmuxMode::SpiCs => "spi_cs",
+            PinmuxMode::SpiSclk => "spi_sclk",
+        }
+    }
+}
+
+pub fn header_pin(pin: BoardPin) -> Option<&'static str> {
+    HEADER_PINS
+        .iter()
+        .chain(SHARED_HEADER_PINS.iter())
+        .find(|(controller, bit, _)| *controller == pin.controller && *bit == pin.bit)
+        .map(|(_, _, name)| *name)
+}
+
+/// Every header pin the board uses along with the mode it must be in.
+pub fn required_modes(board: &Board) -> Result<Vec<(&'static str, PinmuxMode)>, SamError> {
+    let mut pins = Vec::new();
+
+    for adc in &board.adcs {
+        pins.push((adc.cs, PinmuxMode::Gpio));
+
+        if let Some(drdy) = adc.drdy {
+            pins.push((drdy, PinmuxMode::Gpio));
+        }
+    }
+
+    pins.extend(board.idle_cs.iter().map(|pin| (*pin, PinmuxMode::Gpio)));
+    pins.extend(board.start.map(|pin| (pin, PinmuxMode::Gpio)));
+    pins.extend(board.valves.iter().map(|valve| (valve.pin, PinmuxMode::Gpio)));
+
+    // LEDs handed to the kernel's LED driver aren't on the header
+    pins.extend(board.leds.iter().filter(|led| led.sysfs.is_none()).map(|led| (led.pin, PinmuxMode::Gpio)));
+
+    pins.push((board.spi.sclk, PinmuxMode::SpiSclk));
+    pins.push((board.spi.miso, PinmuxMode::Spi));
+    pins.push((board.spi.mosi, PinmuxMode::Spi));
+    pins.push((board.spi.cs, PinmuxMode::SpiCs));
+
+    pins.into_iter()
+        .map(|(pin, mode)| match header_pin(pin) {
+            Some(name) => Ok((name, mode)),
+            None => Err(SamError::NoHeaderPin { controller: pin.controller, bit: pin.bit }),
+        })
+        .collect()
+}
+
+pub struct Pinmux {
+    root: PathBuf,
+}
+
+impl Pinmux {
+    /// Uses the pinmux tree at SAM_PINMUX_ROOT, or the kernel's when unset.
+    pub fn from_env() -> Pinmux {
+        let root = std::env::var("SAM_PINMUX_ROOT").unwrap_or_else(|_| DEFAULT_ROOT.to_string());
+        Pinmux::new(root)
+    }
+
+    pub fn new(root: impl Into<PathBuf>) -> Pinmux {
+        Pinmux { root: root.into() }
+    }
+
+    fn state_path(&self, pin: &str) -> PathBuf {
+        self.root.join(format!("ocp:{pin}_pinmux")).join("state")
+    }
+
+    pub fn mode(&self, pin: &str) -> Result<String, SamError> {
+        fs::read_to_string(self.state_path(pin))
+            .map(|state| state.trim().to_string())
+            .map_err(|source| SamError::Pinmux { pin: pin.to_string(), source })
+    }
+
+    pub fn set_mode(&self, pin: &str, mode: PinmuxMode) -> Result<(), SamError> {
+        // the state file only exists for pins the overlay exposes, so don't
+        // create it
+        fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(self.state_path(pin))
+            .and_then(|mut file| io::Write::write_all(&mut file, mode.as_str().as_bytes()))
+            .map_err(|source| SamError::Pinmux { pin: pin.to_string(), source })
+    }
+
+    /// Muxes every pin the board uses and reads each one back, failing on the
+    /// first pin that isn't in the mode it needs.
+    pub fn apply(&self, board: &Board) -> Result<(), SamError> {
+        let pins = required_modes(board)?;
+
+        for (pin, mode) in &pins {
+            self.set_mode(pin, *mode)?;
+        }
+
+        self.verify(&pins)
+    }
+
+    pub fn verify(&self, pins: &[(&'static str, PinmuxMode)]) -> Result<(), SamError> {
+        for (pin, mode) in pins {
+            let actual = self.mode(pin)?;
+
+            if actual != mode.as_str() {
+                return Err(SamError::Mismuxed { pin: pin.to_string(), expected: mode.as_str(), actual });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Lays out a pinmux tree under `root` with a state file for every pin the
+/// board uses, each starting in the "default" mode.
+pub fn create_fake_tree(root: &Path, board: &Board) -> Result<(), SamError> {
+    for (pin, _) in required_modes(board)? {
+        let directory = root.join(format!("ocp:{pin}_pinmux"));
+
+        fs::create_dir_all(&directory)
+            .and_then(|_| fs::write(directory.join("state"), "default\n"))
+            .map_err(|source| SamError::Pinmux { pin: pin.to_string(), source })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BoardPin;
+
+    fn board() -> Board {
+        Board::parse(include_str!("../board.toml")).unwrap()
+    }
+
+    // a fresh directory for one test's pinmux tree
+    fn fake_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("sam-pinmux-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn apply_muxes_every_pin_the_board_uses() {
+        let board = board();
+        let root = fake_root("apply");
+        create_fake_tree(&root, &board).unwrap();
+
+        let pinmux = Pinmux::new(&root);
+        assert_eq!(pinmux.mode("P9_22").unwrap(), "default");
+
+        pinmux.apply(&board).unwrap();
+
+        for (pin, mode) in required_modes(&board).unwrap() {
+            assert_eq!(pinmux.mode(pin).unwrap(), mode.as_str());
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn verify_catches_a_pin_muxed_elsewhere() {
+        let board = board();
+        let root = fake_root("verify");
+        create_fake_tree(&root, &board).unwrap();
+
+        let pinmux = Pinmux::new(&root);
+        pinmux.apply(&board).unwrap();
+
+        // e.g. something else running config-pin after SAM started
+        fs::write(root.join("ocp:P9_22_pinmux").join("state"), "gpio\n").unwrap();
+
+        let pins = required_modes(&board).unwrap();
+        let error = pinmux.verify(&pins).unwrap_err();
+
+        assert!(matches!(
+            error,
+            SamError::Mismuxed { ref pin, expected: "spi_sclk", ref actual } if pin == "P9_22" && actual == "gpio"
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn pins_off_the_header_are_rejected() {
+        let mut board = board();
+
+        // GPIO1_8 isn't broken out to P8 or P9
+        board.idle_cs.push(BoardPin { controller: 1, bit: 8 });
+
+        assert!(matches!(
+            required_modes(&board),
+            Err(SamError::NoHeaderPin { controller: 1, bit: 8 })
+        ));
+    }
+}
@@ -0,0 +1,218 @@
+//! RTD excitation, wiring and lead-resistance cancellation.
+//!
+//! RTDs are read ratiometrically. An IDAC drives the excitation current
+//! through the RTD and on through a precision reference resistor, and the
+//! voltage across that resistor is the ADC's reference. The conversion is then
+//! the RTD's resistance as a fraction of the reference resistor, however far
+//! the excitation current is from nominal.
+//!
+//!  - 2-wire: one IDAC, and the leads are measured along with the RTD. Their
+//!    resistance is subtracted when it is configured.
+//!  - 3-wire: a second, matched IDAC drives the same current through the third
+//!    lead, which is in the measurement opposite the first lead and cancels it.
+//!    Both currents return through the reference resistor, doubling the
+//!    reference.
+//!  - 4-wire: one IDAC, with separate sense leads that carry no current.
+
+use serde::Deserialize;
+
+use crate::adc::registers::{IdacMag, IdacMagnitude, IdacMux, InpMux, Input, Ref, ReferenceMonitor, ReferenceSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u8")]
+pub enum Wiring {
+    TwoWire,
+    ThreeWire,
+    FourWire,
+}
+
+impl TryFrom<u8> for Wiring {
+    type Error = String;
+
+    fn try_from(wires: u8) -> Result<Wiring, String> {
+        match wires {
+            2 => Ok(Wiring::TwoWire),
+            3 => Ok(Wiring::ThreeWire),
+            4 => Ok(Wiring::FourWire),
+            _ => Err(format!("{wires} is not an RTD wiring, expected 2, 3 or 4")),
+        }
+    }
+}
+
+/// How one RTD channel is wired to the ADC and excited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtdConfig {
+    pub wiring: Wiring,
+    pub excitation: IdacMagnitude,
+    pub positive: Input,
+    pub negative: Input,
+    pub idac1: Input,
+    /// Drives the compensating lead of a 3-wire RTD, unused otherwise.
+    pub idac2: Input,
+    pub reference: ReferenceSource,
+    /// Ohms. Without it, readings keep their uncalibrated voltage scaling.
+    pub reference_resistor: Option<f64>,
+    /// Ohms of both leads together, taken off 2-wire readings.
+    pub lead_resistance: f64,
+}
+
+impl RtdConfig {
+    /// The wiring of the RTD harness on the SAM board: 3-wire RTDs at 1 mA on
+    /// AIN1/AIN2 against REF0 and AIN3/AIN4 against REF1, driven from AIN5 and
+    /// AIN0.
+    pub fn default_for(channel: usize) -> RtdConfig {
+        let (positive, negative, reference) = match channel {
+            0 => (Input::Ain1, Input::Ain2, ReferenceSource::Ref0),
+            _ => (Input::Ain3, Input::Ain4, ReferenceSource::Ref1),
+        };
+
+        RtdConfig {
+            wiring: Wiring::ThreeWire,
+            excitation: IdacMagnitude::Ua1000,
+            positive,
+            negative,
+            idac1: Input::Ain5,
+            idac2: Input::Ain0,
+            reference,
+            reference_resistor: None,
+            lead_resistance: 0.0,
+        }
+    }
+
+    pub fn inpmux(&self) -> InpMux {
+        InpMux::new(self.positive, self.negative)
+    }
+
+    pub fn idac_mag(&self) -> IdacMag {
+        IdacMag::new().magnitude(self.excitation)
+    }
+
+    pub fn idac_mux(&self) -> IdacMux {
+        match self.wiring {
+            Wiring::ThreeWire => IdacMux::new().idac1(self.idac1).idac2(self.idac2),
+            Wiring::TwoWire | Wiring::FourWire => IdacMux::new().idac1(self.idac1),
+        }
+    }
+
+    pub fn reference(&self) -> Ref {
+        Ref::new().monitor(ReferenceMonitor::Threshold).source(self.reference)
+    }
+
+    /// Ohms of the RTD, given its conversion as a fraction of full scale at
+    /// unity gain. None without a reference resistor to scale by.
+    pub fn resistance(&self, fraction: f64) -> Option<f64> {
+        let reference_resistor = self.reference_resistor?;
+
+        Some(match self.wiring {
+            Wiring::TwoWire => fraction * reference_resistor - self.lead_resistance,
+            Wiring::ThreeWire => fraction * reference_resistor * 2.0,
+            Wiring::FourWire => fraction * reference_resistor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adc::registers::Register;
+
+    const RTD: f64 = 110.0;
+    const LEAD: f64 = 1.5;
+    const REFERENCE_RESISTOR: f64 = 1620.0;
+
+    fn config(wiring: Wiring) -> RtdConfig {
+        RtdConfig {
+            wiring,
+            reference_resistor: Some(REFERENCE_RESISTOR),
+            lead_resistance: 2.0 * LEAD,
+            ..RtdConfig::default_for(0)
+        }
+    }
+
+    // what the ADC converts for an RTD behind leads of LEAD ohms each, as a
+    // fraction of full scale: the voltage across the inputs over the voltage
+    // across the reference resistor, per amp of excitation
+    fn conversion(wiring: Wiring) -> f64 {
+        let (measured, reference) = match wiring {
+            // both leads carry the current into the measurement
+            Wiring::TwoWire => (RTD + 2.0 * LEAD, REFERENCE_RESISTOR),
+            // the second current's drop in the third lead cancels the first
+            // lead's, and both currents return through the reference
+            Wiring::ThreeWire => (RTD + LEAD - LEAD, 2.0 * REFERENCE_RESISTOR),
+            // the sense leads carry no current
+            Wiring::FourWire => (RTD, REFERENCE_RESISTOR),
+        };
+
+        measured / reference
+    }
+
+    #[test]
+    fn each_wiring_reads_the_rtd_without_its_leads() {
+        for wiring in [Wiring::TwoWire, Wiring::ThreeWire, Wiring::FourWire] {
+            let ohms = config(wiring).resistance(conversion(wiring)).unwrap();
+
+            assert!((ohms - RTD).abs() < 1e-9, "{wiring:?}: {ohms}");
+        }
+    }
+
+    #[test]
+    fn three_wire_leaves_the_lead_to_the_hardware() {
+        // the second current already took the lead drop off once, so the
+        // configured lead resistance mustn't take it off again
+        let with_leads = config(Wiring::ThreeWire);
+        let without_leads = RtdConfig { lead_resistance: 0.0, ..with_leads };
+        let fraction = conversion(Wiring::ThreeWire);
+
+        assert_eq!(with_leads.resistance(fraction), without_leads.resistance(fraction));
+    }
+
+    #[test]
+    fn resistance_scales_with_the_reference_resistor() {
+        let fraction = 0.25;
+
+        for (reference_resistor, ohms) in [(1000.0, 250.0), (1620.0, 405.0), (4020.0, 1005.0)] {
+            let rtd = RtdConfig { reference_resistor: Some(reference_resistor), ..config(Wiring::FourWire) };
+            assert_eq!(rtd.resistance(fraction), Some(ohms));
+        }
+
+        let unscaled = RtdConfig { reference_resistor: None, ..config(Wiring::FourWire) };
+        assert_eq!(unscaled.resistance(fraction), None);
+    }
+
+    #[test]
+    fn only_three_wire_drives_the_second_current() {
+        let idac_mux = [
+            (Wiring::TwoWire, 0xF5),
+            (Wiring::ThreeWire, 0x05),
+            (Wiring::FourWire, 0xF5),
+        ];
+
+        for (wiring, expected) in idac_mux {
+            assert_eq!(config(wiring).idac_mux().encode(), expected, "{wiring:?}");
+        }
+    }
+
+    #[test]
+    fn excitation_sets_the_current_magnitude() {
+        let idac_mag = [
+            (IdacMagnitude::Ua10, 0x01),
+            (IdacMagnitude::Ua250, 0x04),
+            (IdacMagnitude::Ua500, 0x05),
+            (IdacMagnitude::Ua1000, 0x07),
+            (IdacMagnitude::Ua2000, 0x09),
+        ];
+
+        for (excitation, expected) in idac_mag {
+            let rtd = RtdConfig { excitation, ..RtdConfig::default_for(0) };
+            assert_eq!(rtd.idac_mag().encode(), expected, "{excitation:?}");
+        }
+    }
+
+    #[test]
+    fn default_harness_splits_the_references() {
+        assert_eq!(RtdConfig::default_for(0).inpmux().encode(), 0x12);
+        assert_eq!(RtdConfig::default_for(0).reference().encode(), 0x50);
+        assert_eq!(RtdConfig::default_for(1).inpmux().encode(), 0x34);
+        assert_eq!(RtdConfig::default_for(1).reference().encode(), 0x54);
+    }
+}
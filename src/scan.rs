@@ -0,0 +1,338 @@
+//! Scan scheduling: which ADC channels are read in each frame of the poll
+//! loop, and in what order.
+//!
+//! Every channel is read at its own scan rate from the board definition.
+//! Frames run at the fastest of those rates, and each channel is read every
+//! Nth frame, N being the frame rate over the channel's rate rounded to a
+//! whole number. Channels are staggered across the frames of their period so
+//! each frame holds as few reads as possible, which keeps every read close to
+//! when it is due. The schedule repeats every hyperperiod (the least common
+//! multiple of every period) and is worked out once at startup, so the
+//! sequence of mux changes is the same on every run.
+//!
+//! Channels without a scan rate are read every frame. When no channel has
+//! one, frames run back to back as fast as the ADCs allow.
+
+use std::{collections::{HashMap, HashSet}, thread, time::{Duration, Instant}};
+
+use crate::{board::Board, error::SamError};
+
+// longest schedule worked out ahead of time, plenty for rates that are
+// round numbers of each other
+const MAX_FRAMES: usize = 100_000;
+
+/// One channel read within a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    /// Position of the ADC in the board definition.
+    pub adc: usize,
+    pub channel: usize,
+    /// Channel the same ADC reads next, to be selected once this one is read.
+    pub next: usize,
+}
+
+pub struct Scan {
+    frames: Vec<Vec<Slot>>,
+    period: Option<Duration>,
+    index: usize,
+    deadline: Option<Instant>,
+}
+
+struct Entry {
+    adc: usize,
+    channel: usize,
+    period: usize,
+}
+
+impl Scan {
+    pub fn new(board: &Board) -> Result<Scan, SamError> {
+        let rates: Vec<(usize, usize, Option<f64>)> = board.adcs
+            .iter()
+            .enumerate()
+            .flat_map(|(adc, pins)| {
+                pins.scan_rates()
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(channel, rate)| (adc, channel, rate))
+            })
+            .collect();
+
+        let frame_rate = rates
+            .iter()
+            .filter_map(|(_, _, rate)| *rate)
+            .reduce(f64::max);
+
+        let mut entries: Vec<Entry> = rates
+            .iter()
+            .map(|&(adc, channel, rate)| {
+                let period = match (rate, frame_rate) {
+                    (Some(rate), Some(frame_rate)) => ((frame_rate / rate).round() as usize).max(1),
+                    _ => 1,
+                };
+
+                Entry { adc, channel, period }
+            })
+            .collect();
+
+        let hyperperiod = entries
+            .iter()
+            .try_fold(1, |hyperperiod, entry| {
+                let lcm = hyperperiod / gcd(hyperperiod, entry.period) * entry.period;
+                (lcm <= MAX_FRAMES).then_some(lcm)
+            })
+            .ok_or_else(|| SamError::InvalidBoard(format!(
+                "scan rates don't repeat within {MAX_FRAMES} frames, make them multiples of each other"
+            )))?;
+
+        // fastest channels first, so slower ones fill in around them
+        entries.sort_by_key(|entry| (entry.period, entry.adc, entry.channel));
+
+        let mut load = vec![0; hyperperiod];
+        let mut frames: Vec<Vec<(usize, usize)>> = vec![Vec::new(); hyperperiod];
+
+        for entry in &entries {
+            let phase = (0..entry.period)
+                .min_by_key(|phase| {
+                    (*phase..hyperperiod).step_by(entry.period).map(|frame| load[frame]).max()
+                })
+                .unwrap_or(0);
+
+            for frame in (phase..hyperperiod).step_by(entry.period) {
+                load[frame] += 1;
+                frames[frame].push((entry.adc, entry.channel));
+            }
+        }
+
+        // the same channel of every ADC back to back, so each ADC settles
+        // while the others are read
+        for frame in &mut frames {
+            frame.sort_by_key(|&(adc, channel)| (channel, adc));
+        }
+
+        Ok(Scan {
+            frames: link(&frames),
+            period: frame_rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            index: 0,
+            deadline: None,
+        })
+    }
+
+    /// Frames per second, or None when frames run back to back.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.period.map(|period| 1.0 / period.as_secs_f64())
+    }
+
+    /// Number of frames before the schedule repeats.
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The reads of the next frame.
+    pub fn next_frame(&mut self) -> &[Slot] {
+        let frame = self.index;
+        self.index = (self.index + 1) % self.frames.len();
+
+        &self.frames[frame]
+    }
+
+    /// Sleeps until the next frame is due.
+    pub fn wait(&mut self) {
+        let Some(period) = self.period else {
+            return;
+        };
+
+        let now = Instant::now();
+        let next = self.deadline.map_or(now + period, |deadline| deadline + period);
+
+        // a frame that overran starts the clock over rather than rushing the
+        // ones after it
+        let next = next.max(now);
+
+        self.deadline = Some(next);
+        thread::sleep(next - now);
+    }
+}
+
+/// Splits a frame into rounds that read each ADC at most once, in order. With
+/// the ADCs converting on a shared START/SYNC pulse, every read in a round is
+/// of the same instant.
+pub fn rounds(frame: &[Slot]) -> Vec<&[Slot]> {
+    let mut rounds = Vec::new();
+    let mut start = 0;
+    let mut adcs = HashSet::new();
+
+    for (index, slot) in frame.iter().enumerate() {
+        if !adcs.insert(slot.adc) {
+            rounds.push(&frame[start..index]);
+            start = index;
+
+            adcs.clear();
+            adcs.insert(slot.adc);
+        }
+    }
+
+    if start < frame.len() {
+        rounds.push(&frame[start..]);
+    }
+
+    rounds
+}
+
+// fills in which channel each ADC reads after every slot, wrapping around
+// the end of the schedule
+fn link(frames: &[Vec<(usize, usize)>]) -> Vec<Vec<Slot>> {
+    let mut linked: Vec<Vec<Slot>> = frames
+        .iter()
+        .map(|frame| {
+            frame
+                .iter()
+                .map(|&(adc, channel)| Slot { adc, channel, next: channel })
+                .collect()
+        })
+        .collect();
+
+    // first and latest read of each ADC so far, by frame and slot
+    let mut first: HashMap<usize, (usize, usize)> = HashMap::new();
+    let mut latest: HashMap<usize, (usize, usize)> = HashMap::new();
+
+    for frame in 0..linked.len() {
+        for slot in 0..linked[frame].len() {
+            let Slot { adc, channel, .. } = linked[frame][slot];
+
+            if let Some((previous_frame, previous_slot)) = latest.insert(adc, (frame, slot)) {
+                linked[previous_frame][previous_slot].next = channel;
+            }
+
+            first.entry(adc).or_insert((frame, slot));
+        }
+    }
+
+    for (adc, (frame, slot)) in latest {
+        let (first_frame, first_slot) = first[&adc];
+        linked[frame][slot].next = linked[first_frame][first_slot].channel;
+    }
+
+    linked
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPI: &str = r#"
+        [spi]
+        sclk = { controller = 0, bit = 2 }
+        miso = { controller = 0, bit = 3 }
+        mosi = { controller = 0, bit = 4 }
+        cs = { controller = 0, bit = 5 }
+    "#;
+
+    fn board(adcs: &str) -> Board {
+        Board::parse(&format!("{SPI}\n{adcs}")).unwrap()
+    }
+
+    // how many times each (adc, channel) is read over one hyperperiod
+    fn reads(scan: &mut Scan) -> HashMap<(usize, usize), usize> {
+        let mut reads = HashMap::new();
+
+        for _ in 0..scan.frames() {
+            for slot in scan.next_frame() {
+                *reads.entry((slot.adc, slot.channel)).or_insert(0) += 1;
+            }
+        }
+
+        reads
+    }
+
+    #[test]
+    fn mixed_rates_repeat_at_the_lcm_of_their_periods() {
+        // periods of 1, 2 and 3 frames at 60 frames a second
+        let mut scan = Scan::new(&board(r#"
+            [[adc]]
+            measurement = "IPower"
+            cs = { controller = 2, bit = 15 }
+            scan_rate = 60.0
+
+            [[adc]]
+            measurement = "Rtd"
+            cs = { controller = 2, bit = 11 }
+            scan_rate = 30.0
+
+            [[adc.channel]]
+            index = 1
+            scan_rate = 20.0
+        "#)).unwrap();
+
+        assert!((scan.frame_rate().unwrap() - 60.0).abs() < 1e-3);
+        assert_eq!(scan.frames(), 6);
+
+        let reads = reads(&mut scan);
+        assert_eq!(reads[&(0, 0)], 6);
+        assert_eq!(reads[&(0, 1)], 6);
+        assert_eq!(reads[&(1, 0)], 3);
+        assert_eq!(reads[&(1, 1)], 2);
+    }
+
+    #[test]
+    fn channels_without_a_rate_are_read_every_frame() {
+        let mut scan = Scan::new(&board(r#"
+            [[adc]]
+            measurement = "IPower"
+            cs = { controller = 2, bit = 15 }
+        "#)).unwrap();
+
+        assert_eq!(scan.frame_rate(), None);
+        assert_eq!(scan.frames(), 1);
+        assert_eq!(scan.next_frame(), [
+            Slot { adc: 0, channel: 0, next: 1 },
+            Slot { adc: 0, channel: 1, next: 0 },
+        ]);
+    }
+
+    #[test]
+    fn schedules_past_the_frame_cap_are_rejected() {
+        // periods of 7, 11, 13, 17 and 19 frames repeat every 323323. Parsed
+        // without validating, which would reject the schedule itself
+        let board: Board = toml::from_str(&[SPI, r#"
+            [[adc]]
+            measurement = "CurrentLoopPt"
+            cs = { controller = 0, bit = 30 }
+            scan_rate = 1000.0
+
+            [[adc.channel]]
+            index = 1
+            scan_rate = 142.857
+
+            [[adc.channel]]
+            index = 2
+            scan_rate = 90.909
+
+            [[adc.channel]]
+            index = 3
+            scan_rate = 76.923
+
+            [[adc.channel]]
+            index = 4
+            scan_rate = 58.824
+
+            [[adc.channel]]
+            index = 5
+            scan_rate = 52.632
+        "#].concat()).unwrap();
+
+        assert!(matches!(Scan::new(&board), Err(SamError::InvalidBoard(_))));
+    }
+
+    #[test]
+    fn rounds_read_each_adc_at_most_once() {
+        let slot = |adc, channel| Slot { adc, channel, next: channel };
+        let frame = [slot(0, 0), slot(1, 0), slot(2, 0), slot(0, 1), slot(1, 1), slot(0, 2)];
+
+        assert_eq!(rounds(&frame), [&frame[0..3], &frame[3..5], &frame[5..6]]);
+        assert!(rounds(&[]).is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+use spidev::spidevioctl::SpidevTransfer;
+use spidev::{SpiModeFlags, Spidev, SpidevOptions};
+use std::io;
+
+use crate::error::SamError;
+
+/// Full-duplex SPI bus the ADCs are reached through. Chip select is handled
+/// separately over GPIO, so a transport only has to clock bytes.
+pub trait SpiTransport {
+    /// Clocks out `tx` while filling `rx` with what the device shifts back.
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> io::Result<()>;
+
+    fn write(&self, tx: &[u8]) -> io::Result<()> {
+        let mut rx = vec![0; tx.len()];
+        self.transfer(tx, &mut rx)
+    }
+}
+
+impl SpiTransport for Spidev {
+    fn transfer(&self, tx: &[u8], rx: &mut [u8]) -> io::Result<()> {
+        let mut transfer = SpidevTransfer::read_write(tx, rx);
+        Spidev::transfer(self, &mut transfer)
+    }
+
+    fn write(&self, tx: &[u8]) -> io::Result<()> {
+        let mut transfer = SpidevTransfer::write(tx);
+        Spidev::transfer(self, &mut transfer)
+    }
+}
+
+/// Opens and configures the SPI bus shared by the ADCs.
+pub fn open_spidev(path: &str) -> Result<Spidev, SamError> {
+    let mut spidev = Spidev::open(path)
+        .map_err(|source| SamError::Spi { path: path.to_string(), source })?;
+
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(10_000_000)
+        .lsb_first(false)
+        .mode(SpiModeFlags::SPI_MODE_1)
+        .build();
+
+    spidev.configure(&options)
+        .map_err(|source| SamError::Spi { path: path.to_string(), source })?;
+
+    Ok(spidev)
+}
@@ -1,20 +1,42 @@
-use std::{net::{SocketAddr, UdpSocket}, sync::Arc, thread, time::Instant};
+use std::{collections::HashMap, net::{SocketAddr, UdpSocket}, sync::{mpsc::Receiver, Arc}, thread, time::{Duration, Instant}};
 use common::comm::{DataPoint, DataMessage};
-use spidev::{SpiModeFlags, Spidev, SpidevOptions};
 use std::rc::Rc;
 use hostname;
 use std::net::ToSocketAddrs;
-use crate::{adc::{self, gpio_controller_mappings, pull_gpios_high, data_ready_mappings, ADC}, 
-            data::{generate_data_point, serialize_data}, 
-            gpio::Gpio};
+use crate::{adc::{self, gpio_controller_mappings, pull_gpios_high, data_ready_mappings, RejectedSamples, ADC}, 
+            board::Board,
+            calibration::{CalibrationStore, Request, Routine},
+            data::{generate_data_point, generate_health_points, generate_raw_point, serialize_data, serialize_diagnostics, AdcDiagnostics, Diagnostics}, 
+            error::SamError,
+            gpio::Gpio,
+            pinmux::Pinmux,
+            scan::{self, Scan, Slot},
+            spi::open_spidev,
+            transfer::TransferFile};
 use jeflog::{task, pass, fail, warn};
-use crate::gpio::{PinMode::Output, PinValue::Low};
+use crate::gpio::{Pin, PinGroup, PinMode::Output, PinValue::{High, Low}};
 
 const FC_ADDR: &str = "server-01";
 const HOSTNAMES: [&str; 1] = [FC_ADDR];
 
 const FC_HEARTBEAT_TIMEOUT: u128 = 500;
 
+const DATA_PORT: u16 = 4573;
+
+// port the flight computer takes diagnostics on, next to its data port
+const DIAGNOSTICS_PORT: u16 = 4574;
+
+const SPI_DEVICE: &str = "/dev/spidev0.0";
+const FAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// how long START/SYNC is held high to start a conversion, at least four
+// periods of the ADCs' 4.096 MHz clock
+const START_PULSE: Duration = Duration::from_micros(2);
+
+// how often one ADC's registers are read back while polling, round robin,
+// and calibration requests are checked for
+const READBACK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Data {
     pub data_socket: UdpSocket,
     flight_computer: Option<SocketAddr>,
@@ -23,21 +45,53 @@ pub struct Data {
     curr_measurement: Option<adc::Measurement>,
     data_points: Vec<DataPoint>,
     board_id: Option<String>,
-    gpio_controllers: Vec<Arc<Gpio>>
+    gpio_controllers: Vec<Arc<Gpio>>,
+    board: Arc<Board>,
+    last_readback: Instant,
+    readback_index: usize,
+    health_index: usize,
+    scan: Scan,
+    // START/SYNC pin shared by the ADCs, when they convert on it
+    start: Option<Rc<Pin>>,
+    calibration: Option<CalibrationStore>,
+    // calibration requests sent over the network
+    calibration_requests: Option<Receiver<Request>>,
+    // rejected sample counts as of the last report
+    rejected: HashMap<adc::Measurement, RejectedSamples>,
 }
 
 impl Data {
-    pub fn new(gpio_controllers: Vec<Arc<Gpio>>) -> Data {
-        Data {
-            data_socket: UdpSocket::bind(("0.0.0.0", 4573)).expect("Could not bind client socket"),
+    pub fn new(gpio_controllers: Vec<Arc<Gpio>>, board: Arc<Board>) -> Result<Data, SamError> {
+        let data_socket = UdpSocket::bind(("0.0.0.0", DATA_PORT))
+            .map_err(|source| SamError::Socket { port: DATA_PORT, source })?;
+        let scan = Scan::new(&board)?;
+
+        Ok(Data {
+            data_socket,
             flight_computer: None,
             adcs: None,
             state_num: 0,
             curr_measurement: None,
             data_points: Vec::with_capacity(60),
             board_id: None,
-            gpio_controllers: gpio_controllers
-        }
+            gpio_controllers: gpio_controllers,
+            board,
+            last_readback: Instant::now(),
+            readback_index: 0,
+            health_index: 0,
+            scan,
+            start: None,
+            calibration: None,
+            calibration_requests: None,
+            rejected: HashMap::new(),
+        })
+    }
+
+    /// Runs calibration requests received on `requests` while polling, along
+    /// with those in the calibration directory.
+    pub fn with_calibration_requests(mut self, requests: Receiver<Request>) -> Data {
+        self.calibration_requests = Some(requests);
+        self
     }
 }
 
@@ -49,7 +103,8 @@ pub enum State {
     DeviceDiscovery,
     Identity,
     InitAdcs,
-    PollAdcs
+    PollAdcs,
+    Fault
 }
 
 impl State {
@@ -61,49 +116,86 @@ impl State {
 
         match self {
             State::Init => {
+                // Don't touch the bus until every pin is routed where it belongs
+                if let Err(error) = Pinmux::from_env().apply(&data.board) {
+                    return fault(data, error);
+                }
+
                 /* Create a spidev wrapper to work with
                 you call this wrapper to handle and all transfers */
-                let mut spidev = Spidev::open("/dev/spidev0.0").unwrap();
-
-                let options = SpidevOptions::new()
-                    .bits_per_word(8)
-                    .max_speed_hz(10_000_000)
-                    .lsb_first(false)
-                    .mode(SpiModeFlags::SPI_MODE_1)
-                    .build();
-                spidev.configure(&options).unwrap();
+                let spidev = match open_spidev(SPI_DEVICE) {
+                    Ok(spidev) => spidev,
+                    Err(error) => return fault(data, error),
+                };
 
                 let ref_spidev: Rc<_> = Rc::new(spidev);
-                let ref_controllers = Rc::new(gpio_controller_mappings(&data.gpio_controllers));
-                let ref_drdy = Rc::new(data_ready_mappings(&data.gpio_controllers));
+                let ref_controllers = match gpio_controller_mappings(&data.gpio_controllers, &data.board) {
+                    Ok(mappings) => Rc::new(mappings),
+                    Err(error) => return fault(data, error),
+                };
+                let ref_drdy = match data_ready_mappings(&data.gpio_controllers, &data.board) {
+                    Ok(mappings) => Rc::new(mappings),
+                    Err(error) => return fault(data, error),
+                };
         
-                // Instantiate all measurement types
-                let ds = ADC::new(adc::Measurement::DiffSensors, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let cl = ADC::new(adc::Measurement::CurrentLoopPt, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let board_power = ADC::new(adc::Measurement::VPower, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let board_current = ADC::new(adc::Measurement::IPower, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let vvalve = ADC::new(adc::Measurement::VValve, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let ivalve = ADC::new(adc::Measurement::IValve, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let rtd = ADC::new(adc::Measurement::Rtd, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let tc1 = ADC::new(adc::Measurement::Tc1, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-                let tc2 = ADC::new(adc::Measurement::Tc2, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
-
-                let mut adcs: Vec<adc::ADC> = Vec::with_capacity(9);
- 
-                adcs.push(ds);
-                adcs.push(cl);
-                adcs.push(board_power);
-                adcs.push(board_current);
-                adcs.push(vvalve);
-                adcs.push(ivalve);
-                adcs.push(rtd);
-                adcs.push(tc1);
-                adcs.push(tc2);
-
-                pull_gpios_high(&data.gpio_controllers);
+                // Instantiate every ADC on the board, in board order
+                let synchronized = data.board.start.is_some();
+                let mut adcs: Vec<adc::ADC> = data.board.adcs
+                    .iter()
+                    .map(|pins| {
+                        let mut adc = ADC::new(pins.measurement, ref_spidev.clone(), ref_controllers.clone(), ref_drdy.clone());
+                        adc.set_part(pins.part);
+                        adc.configure_channels(pins.channel_configs());
+                        adc.set_acquisition(pins.acquisition);
+                        adc.configure_rtds(pins.rtd_configs());
+                        adc.configure_transducers(pins.transducers());
+                        adc.set_synchronized(synchronized);
+                        adc
+                    })
+                    .collect();
+
+                // START/SYNC idles low, and every pulse on it converts each
+                // ADC once
+                data.start = match data.board.start {
+                    Some(start) => {
+                        let pin = start.get(&data.gpio_controllers);
+
+                        if let Err(error) = pin.mode(Output).and_then(|()| pin.digital_write(Low)) {
+                            return fault(data, error);
+                        }
+
+                        Some(Rc::new(pin))
+                    }
+                    None => None,
+                };
+
+                // restore the last calibration of every channel
+                data.calibration = match CalibrationStore::from_env() {
+                    Ok(store) => {
+                        restore_calibration(&mut adcs, &store);
+                        Some(store)
+                    }
+                    Err(error) => {
+                        fail!("Could not load ADC calibration, running uncalibrated: {}", error);
+                        None
+                    }
+                };
+
+                // and the transfer function of any channel not left at its default
+                match TransferFile::from_env() {
+                    Ok(Some(file)) => apply_transfers(&mut adcs, file),
+                    Ok(None) => {}
+                    Err(error) => fail!("Could not load transfer functions, using defaults: {}", error),
+                }
+
+                if let Err(error) = pull_gpios_high(&data.gpio_controllers, &data.board) {
+                    return fault(data, error);
+                }
                 
                 data.adcs = Some(adcs);
-                data.data_socket.set_nonblocking(true).expect("set_nonblocking call failed");
+                if let Err(source) = data.data_socket.set_nonblocking(true) {
+                    return fault(data, SamError::Socket { port: DATA_PORT, source });
+                }
 
                 data.board_id = get_board_id();
 
@@ -160,10 +252,11 @@ impl State {
     
                                         let socket_copy = data.data_socket.try_clone();
                                         let controllers = data.gpio_controllers.clone();
+                                        let board = data.board.clone();
 
                                         // Spawn heartbeat thread
                                         thread::spawn(move || {
-                                            monitor_heartbeat(socket_copy.ok().unwrap(), &controllers);
+                                            monitor_heartbeat(socket_copy.ok().unwrap(), &controllers, &board);
                                         });
 
                                         return State::PollAdcs;
@@ -183,58 +276,76 @@ impl State {
 
             State::InitAdcs => {
                 for adc in data.adcs.as_mut().unwrap() {
-                    adc.init_gpio(data.curr_measurement);
+                    if let Err(error) = adc.init_gpio(data.curr_measurement) {
+                        return fault(data, error);
+                    }
+
                     data.curr_measurement = Some(adc.measurement);
-                    adc.reset_status();
 
-                    adc.init_regs();
-                    adc.start_conversion();
+                    // a faulted ADC is reported to the FC and retried by the readback
+                    if let Err(error) = adc.initialize() {
+                        fail!("Could not initialize {:?} ADC, marking it faulted: {}", adc.measurement, error);
+                        continue;
+                    }
+
+                    let calibrations = data.board
+                        .adc(adc.measurement)
+                        .map(|pins| pins.startup_calibrations())
+                        .unwrap_or_default();
+
+                    for (channel, routine) in &calibrations {
+                        run_calibration(adc, data.calibration.as_mut(), *channel, *routine);
+                    }
 
-                    adc.write_iteration(0);
+                    // calibrating leaves the last channel selected
+                    if !calibrations.is_empty() {
+                        adc.write_iteration(0);
+                    }
                 }
                 
                 pass!("Initialized ADCs");
+
+                match data.scan.frame_rate() {
+                    Some(rate) => pass!("Scanning at {} frames a second, repeating every {} frames.", rate, data.scan.frames()),
+                    None => pass!("Scanning every channel back to back."),
+                }
                 State::Identity
             }
 
             State::PollAdcs => {
                 data.data_points.clear();
+
+                if data.last_readback.elapsed() >= READBACK_INTERVAL {
+                    data.last_readback = Instant::now();
+                    readback_next_adc(data);
+                    sweep_next_adc_health(data);
+                    report_rejected_samples(data);
+                    check_thermocouples(data);
+                    send_diagnostics(data);
+                    handle_calibration_requests(data);
+                }
                 
-                for i in 0..6 {
-                    for adc in data.adcs.as_mut().unwrap() {
-                        if (i > 2 && adc.measurement == adc::Measurement::DiffSensors) || 
-                           (i > 4 && adc.measurement == adc::Measurement::VPower) ||
-                           (i > 1 && (adc.measurement == adc::Measurement::IPower || adc.measurement == adc::Measurement::Rtd)) ||
-                           (i > 3 && (adc.measurement ==  adc::Measurement::Tc1 || adc.measurement ==  adc::Measurement::Tc2)) {
-                            continue;
-                        }
+                let frame = data.scan.next_frame().to_vec();
 
-                        adc.init_gpio(data.curr_measurement);
-                        data.curr_measurement = Some(adc.measurement);
-                        
-                        // Read ADC
-                        let (raw_value, unix_timestamp) = adc.get_adc_reading(i);
-                        
-                        // Write ADC for next iteration
-                        adc.write_iteration(i + 1);
-                        
-                        // Don't add ambient temp reading to FC message 
-                        if  i == 0 && (adc.measurement ==  adc::Measurement::Tc1 || adc.measurement ==  adc::Measurement::Tc2) {
-                            continue;
-                        }
+                match data.start.clone() {
+                    Some(start) => {
+                        for round in scan::rounds(&frame) {
+                            start_round(data, &start, round);
 
-                        let data_point = generate_data_point(
-                            raw_value, 
-                            unix_timestamp, 
-                            i,
-                            adc.measurement.clone(), 
-                        );
-    
-                        data.data_points.push(data_point)
+                            for slot in round {
+                                read_slot(data, slot);
+                            }
+                        }
+                    }
+                    None => {
+                        for slot in &frame {
+                            read_slot(data, slot);
+                        }
                     }
                 }
                 
-                if let Some(board_id) = data.board_id.clone() {
+                // a frame of nothing but cold junctions has nothing to send
+                if let Some(board_id) = data.board_id.clone().filter(|_| !data.data_points.is_empty()) {
                     let serialized = serialize_data(board_id, &data.data_points);
 
                     if let Some(socket_addr) = data.flight_computer {
@@ -243,13 +354,357 @@ impl State {
                         .expect("couldn't send data to flight computer");
                     }
                 }
+
+                data.scan.wait();
                 State::PollAdcs
             }
+
+            State::Fault => {
+                // valves were made safe on the way in, wait and start over
+                thread::sleep(FAULT_RETRY_DELAY);
+                State::Init
+            }
+        }
+    }
+}
+
+fn fault(data: &Data, error: SamError) -> State {
+    fail!("Hardware initialization failed: {}", error);
+    warn!("Putting valves in a safe state and retrying in {:?}.", FAULT_RETRY_DELAY);
+
+    safe_valves(&data.gpio_controllers, &data.board);
+    State::Fault
+}
+
+// Pulls the chip select of `adc` low and that of the ADC selected before it
+// high. False when the chip selects can't be written, and so the ADC can't be
+// talked to.
+fn select(adc: &mut adc::ADC, current: &mut Option<adc::Measurement>) -> bool {
+    match adc.init_gpio(*current) {
+        Ok(()) => {
+            *current = Some(adc.measurement);
+            true
+        }
+        Err(error) => {
+            fail!("Could not select {:?} ADC: {}", adc.measurement, error);
+            false
+        }
+    }
+}
+
+// reads one slot of the scan into the frame's data points, and selects the
+// channel its ADC reads next
+fn read_slot(data: &mut Data, slot: &Slot) {
+    let adc = &mut data.adcs.as_mut().unwrap()[slot.adc];
+    let i = slot.channel as u64;
+
+    // NaN tells the FC the channel exists but its ADC is faulted
+    if adc.is_faulted() {
+        if !adc.measurement.is_cold_junction(slot.channel) {
+            data.data_points.push(generate_data_point(f64::NAN, 0.0, i, adc.measurement));
+        }
+
+        return;
+    }
+
+    // an ADC that can't be selected can't be read either
+    if !select(adc, &mut data.curr_measurement) {
+        if !adc.measurement.is_cold_junction(slot.channel) {
+            data.data_points.push(generate_data_point(f64::NAN, 0.0, i, adc.measurement));
+        }
+
+        return;
+    }
+    
+    // Read ADC and select the channel it reads next
+    let reading = adc.read_and_advance(i, slot.next as u64);
+
+    // an ADC that reset itself has to be configured again
+    // before it can go on to the next channel
+    if let Err(SamError::AdcReset { .. }) = reading {
+        warn!("{:?} ADC reset itself, re-initializing.", adc.measurement);
+
+        if let Err(error) = adc.initialize() {
+            fail!("Could not re-initialize {:?} ADC: {}", adc.measurement, error);
         }
+
+        adc.write_iteration(slot.next as u64);
     }
+
+    // Don't add ambient temp reading to FC message 
+    if adc.measurement.is_cold_junction(slot.channel) {
+        return;
+    }
+
+    // a rejected sample goes out as NaN rather than stalling
+    // telemetry, and is counted in the ADC's rejected samples.
+    // So does an open thermocouple, whose reading is noise
+    let (raw_value, unix_timestamp) = match reading {
+        Ok(reading) if !adc.is_open_circuit(i) => reading,
+        _ => (f64::NAN, 0.0),
+    };
+
+    // transducers go out in psi, with the volts they were converted from
+    // alongside
+    let Some(transducer) = adc.transducer(i) else {
+        data.data_points.push(generate_data_point(raw_value, unix_timestamp, i, adc.measurement));
+        return;
+    };
+
+    data.data_points.push(generate_data_point(transducer.pressure(raw_value), unix_timestamp, i, adc.measurement));
+    data.data_points.push(generate_raw_point(raw_value, unix_timestamp, i, adc.measurement));
 }
 
-fn monitor_heartbeat(socket: UdpSocket, gpio_controllers: &Vec<Arc<Gpio>>) {
+// settles every ADC in a round on its channel, then pulses START/SYNC so
+// they all convert at the same instant
+fn start_round(data: &mut Data, start: &Pin, round: &[Slot]) {
+    let adcs = data.adcs.as_mut().unwrap();
+
+    for slot in round {
+        let adc = &mut adcs[slot.adc];
+
+        if adc.is_faulted() {
+            continue;
+        }
+
+        if !select(adc, &mut data.curr_measurement) {
+            continue;
+        }
+
+        // a channel that didn't settle is still read, the count of rejected
+        // samples already has it
+        let _ = adc.settle(slot.channel as u64);
+    }
+
+    // the round is still read without the pulse, and ADCs that wait on DRDY
+    // reject it as a timeout
+    let pulse = start.digital_write(High).and_then(|()| {
+        thread::sleep(START_PULSE);
+        start.digital_write(Low)
+    });
+
+    if let Err(error) = pulse {
+        fail!("Could not pulse START/SYNC: {}", error);
+    }
+
+    let started = Instant::now();
+
+    for slot in round {
+        adcs[slot.adc].started(started);
+    }
+}
+
+// Reads back the registers of the next ADC in turn, re-initializing it if
+// they no longer match what was written, e.g. after a brownout reset. Faulted
+// ADCs get another go at initialization.
+fn readback_next_adc(data: &mut Data) {
+    let adcs = data.adcs.as_mut().unwrap();
+    let index = data.readback_index % adcs.len().max(1);
+    data.readback_index = index + 1;
+
+    let Some(adc) = adcs.get_mut(index) else {
+        return;
+    };
+
+    if !select(adc, &mut data.curr_measurement) {
+        return;
+    }
+
+    if !adc.is_faulted() {
+        match adc.verify_regs() {
+            Ok(()) => return,
+            Err(error) => warn!("{:?} ADC lost its configuration ({}), re-initializing.", adc.measurement, error),
+        }
+    }
+
+    match adc.initialize() {
+        Ok(()) => pass!("Re-initialized {:?} ADC.", adc.measurement),
+        Err(error) => fail!("Could not re-initialize {:?} ADC: {}", adc.measurement, error),
+    }
+}
+
+// Reads the system monitors of the next ADC in turn and adds them to the
+// outgoing data as board-health channels.
+fn sweep_next_adc_health(data: &mut Data) {
+    let adcs = data.adcs.as_mut().unwrap();
+    let index = data.health_index % adcs.len().max(1);
+    data.health_index = index + 1;
+
+    let Some(adc) = adcs.get_mut(index) else {
+        return;
+    };
+
+    if adc.is_faulted() || !select(adc, &mut data.curr_measurement) {
+        return;
+    }
+
+    match adc.read_health() {
+        Ok(health) => data.data_points.extend(generate_health_points(index, &health, 0.0)),
+        Err(error) => warn!("Could not read {:?} ADC health: {}", adc.measurement, error),
+    }
+}
+
+// Checks every thermocouple ADC for open thermocouples.
+fn check_thermocouples(data: &mut Data) {
+    for adc in data.adcs.as_mut().unwrap() {
+        if adc.is_faulted() || !matches!(adc.measurement, adc::Measurement::Tc1 | adc::Measurement::Tc2) {
+            continue;
+        }
+
+        if !select(adc, &mut data.curr_measurement) {
+            continue;
+        }
+
+        if let Err(error) = adc.check_thermocouples() {
+            warn!("Could not check {:?} thermocouples: {}", adc.measurement, error);
+        }
+    }
+}
+
+// Logs how many samples each ADC has rejected since the last report, if any.
+fn report_rejected_samples(data: &mut Data) {
+    for adc in data.adcs.as_ref().unwrap() {
+        let rejected = adc.rejected();
+        let reported = data.rejected.insert(adc.measurement, rejected).unwrap_or_default();
+
+        if rejected.total() == reported.total() {
+            continue;
+        }
+
+        warn!(
+            "{:?} ADC rejected {} samples: {} bad CRC, {} flagged in status, {} resets, {} data ready timeouts.",
+            adc.measurement,
+            rejected.total() - reported.total(),
+            rejected.crc - reported.crc,
+            rejected.status - reported.status,
+            rejected.resets - reported.resets,
+            rejected.timeouts - reported.timeouts,
+        );
+    }
+}
+
+// Sends the diagnostics of every ADC to the flight computer.
+fn send_diagnostics(data: &Data) {
+    let (Some(board_id), Some(flight_computer)) = (data.board_id.clone(), data.flight_computer) else {
+        return;
+    };
+
+    let adcs = data.adcs
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|adc| AdcDiagnostics {
+            measurement: adc.measurement,
+            rejected: adc.rejected(),
+            open_circuit: adc.open_circuit().to_vec(),
+        })
+        .collect();
+
+    let serialized = match serialize_diagnostics(&Diagnostics { board_id, adcs }) {
+        Ok(serialized) => serialized,
+        Err(error) => {
+            fail!("Could not serialize diagnostics: {}", error);
+            return;
+        }
+    };
+
+    let address = SocketAddr::new(flight_computer.ip(), DIAGNOSTICS_PORT);
+
+    if let Err(error) = data.data_socket.send_to(&serialized, address) {
+        warn!("Could not send diagnostics to the flight computer: {}", error);
+    }
+}
+
+fn restore_calibration(adcs: &mut [adc::ADC], store: &CalibrationStore) {
+    for record in store.records() {
+        let Some(adc) = adcs.iter_mut().find(|adc| adc.measurement == record.measurement) else {
+            warn!("Ignoring calibration of {:?} channel {}, there is no such ADC.", record.measurement, record.channel);
+            continue;
+        };
+
+        if record.part != adc.part() {
+            warn!("Ignoring calibration of {:?} channel {}, it was measured on an {:?}.", record.measurement, record.channel, record.part);
+            continue;
+        }
+
+        if let Err(error) = adc.set_calibration(record.channel, record.coefficients()) {
+            warn!("Ignoring calibration from {}: {}", record.date, error);
+        }
+    }
+}
+
+fn apply_transfers(adcs: &mut [adc::ADC], file: TransferFile) {
+    pass!("Using transfer functions version {} from {}.", file.version, file.date);
+
+    for entry in file.channels {
+        let Some(adc) = adcs.iter_mut().find(|adc| adc.measurement == entry.measurement) else {
+            warn!("Ignoring transfer function of {:?} channel {}, there is no such ADC.", entry.measurement, entry.channel);
+            continue;
+        };
+
+        if let Err(error) = adc.set_transfer(entry.channel, entry.transfer) {
+            warn!("Ignoring transfer function: {}", error);
+        }
+    }
+}
+
+// Runs the calibration routines requested over the network or through the
+// calibration store, one channel at a time, and leaves each ADC back at the
+// start of its scan.
+fn handle_calibration_requests(data: &mut Data) {
+    let mut requests: Vec<Request> = match &data.calibration_requests {
+        Some(received) => received.try_iter().collect(),
+        None => Vec::new(),
+    };
+
+    if let Some(store) = &data.calibration {
+        match store.take_requests() {
+            Ok(stored) => requests.extend(stored),
+            Err(error) => fail!("Could not read calibration requests: {}", error),
+        }
+    }
+
+    for request in requests {
+        let adcs = data.adcs.as_mut().unwrap();
+        let Some(adc) = adcs.iter_mut().find(|adc| adc.measurement == request.measurement) else {
+            fail!("Cannot calibrate {:?}, there is no such ADC.", request.measurement);
+            continue;
+        };
+
+        if !select(adc, &mut data.curr_measurement) {
+            continue;
+        }
+
+        run_calibration(adc, data.calibration.as_mut(), request.channel, request.routine);
+        adc.write_iteration(0);
+    }
+}
+
+fn run_calibration(adc: &mut adc::ADC, store: Option<&mut CalibrationStore>, channel: usize, routine: Routine) {
+    let coefficients = match adc.calibrate(channel, routine) {
+        Ok(coefficients) => coefficients,
+        Err(error) => {
+            fail!("{:?} calibration of {:?} channel {} failed: {}", routine, adc.measurement, channel, error);
+            return;
+        }
+    };
+
+    pass!(
+        "{:?} calibration of {:?} channel {}: offset {}, full scale {:#06x}.",
+        routine, adc.measurement, channel, coefficients.offset, coefficients.full_scale
+    );
+
+    match store {
+        Some(store) => {
+            if let Err(error) = store.save(adc.measurement, channel, adc.part(), routine, coefficients) {
+                fail!("Could not save calibration: {}", error);
+            }
+        }
+        None => warn!("No calibration store, {:?} channel {} calibration will not persist.", adc.measurement, channel),
+    }
+}
+
+fn monitor_heartbeat(socket: UdpSocket, gpio_controllers: &[Arc<Gpio>], board: &Board) {
     let mut buf = [0; 65536];
     let mut last_heartbeat = Instant::now();
 
@@ -286,23 +741,26 @@ fn monitor_heartbeat(socket: UdpSocket, gpio_controllers: &Vec<Arc<Gpio>>) {
             }
         }    
     }
-    abort(gpio_controllers);
+    abort(gpio_controllers, board);
 }
 
-fn abort(controllers: &Vec<Arc<Gpio>>) {
+fn abort(controllers: &[Arc<Gpio>], board: &Board) {
     fail!("Aborting the SAM Board.");
     warn!("You must manually restart SAM software.");
 
-    let pins = vec![controllers[0].get_pin(8),  // valve 1
-                    controllers[2].get_pin(16), // valve 2
-                    controllers[2].get_pin(17), // valve 3
-                    controllers[2].get_pin(25), // valve 4
-                    controllers[2].get_pin(1),  // valve 5
-                    controllers[1].get_pin(14)];// valve 6
+    safe_valves(controllers, board);
+}
+
+// de-energizes every valve, all valves on a bank at once
+fn safe_valves(controllers: &[Arc<Gpio>], board: &Board) {
+    let pins: Vec<Pin> = board.valves.iter().map(|valve| valve.pin.get(controllers)).collect();
+    let valves = PinGroup::new(&pins);
+
+    // latch the outputs low before enabling the drivers so no valve blips on
+    let safed = valves.digital_write(Low).and(valves.mode(Output));
 
-    for pin in pins.iter() {
-        pin.mode(Output);
-        pin.digital_write(Low);
+    if let Err(error) = safed {
+        fail!("Could not put every valve in a safe state: {}", error);
     }
 }
 
@@ -0,0 +1,113 @@
+//! Current-loop pressure transducers.
+//!
+//! A 4-20 mA transducer sinks a current proportional to pressure through a
+//! sense resistor, and the ADC reads the voltage across it. Each transducer's
+//! sense resistance, current span and pressure range are set per channel in
+//! the board definition, so a sensor swap only needs a config change. Readings
+//! are converted to psi whatever units the transducer is rated in. The volts
+//! across the sense resistor are sent alongside (see src/data.rs), so a dead
+//! loop can be told apart from a low pressure.
+
+use serde::Deserialize;
+
+const PSI_PER_BAR: f64 = 14.503_773_8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PressureUnit {
+    #[default]
+    Psi,
+    Bar,
+    Kpa,
+    Mpa,
+}
+
+impl PressureUnit {
+    pub fn psi(&self) -> f64 {
+        match self {
+            PressureUnit::Psi => 1.0,
+            PressureUnit::Bar => PSI_PER_BAR,
+            PressureUnit::Kpa => PSI_PER_BAR / 100.0,
+            PressureUnit::Mpa => PSI_PER_BAR * 10.0,
+        }
+    }
+}
+
+/// One transducer on a current-loop channel.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transducer {
+    /// Ohms.
+    pub sense_resistor: f64,
+    /// Milliamps at the bottom and top of the pressure range.
+    #[serde(default = "default_span")]
+    pub current_span: [f64; 2],
+    /// Pressure at the bottom and top of the current span, in `units`.
+    pub pressure_range: [f64; 2],
+    #[serde(default)]
+    pub units: PressureUnit,
+}
+
+fn default_span() -> [f64; 2] {
+    [4.0, 20.0]
+}
+
+impl Transducer {
+    /// Milliamps through the sense resistor, given the volts across it.
+    pub fn current(&self, volts: f64) -> f64 {
+        volts / self.sense_resistor * 1000.0
+    }
+
+    /// Psi, given the volts across the sense resistor. Currents outside the
+    /// span extrapolate, so a dead loop reads well below the range.
+    pub fn pressure(&self, volts: f64) -> f64 {
+        let [low, high] = self.current_span;
+        let [bottom, top] = self.pressure_range;
+        let fraction = (self.current(volts) - low) / (high - low);
+
+        (bottom + fraction * (top - bottom)) * self.units.psi()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transducer(current_span: [f64; 2], units: PressureUnit) -> Transducer {
+        Transducer { sense_resistor: 250.0, current_span, pressure_range: [0.0, 100.0], units }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn pressure_spans_4_to_20_ma() {
+        let psi = transducer(default_span(), PressureUnit::Psi);
+
+        // 4, 12 and 20 mA through 250 ohms
+        assert_close(psi.pressure(1.0), 0.0);
+        assert_close(psi.pressure(3.0), 50.0);
+        assert_close(psi.pressure(5.0), 100.0);
+
+        // a dead loop reads well below the range
+        assert_close(psi.pressure(0.0), -25.0);
+    }
+
+    #[test]
+    fn pressure_converts_to_psi() {
+        assert_close(transducer(default_span(), PressureUnit::Bar).pressure(5.0), 100.0 * PSI_PER_BAR);
+        assert_close(transducer(default_span(), PressureUnit::Kpa).pressure(5.0), PSI_PER_BAR);
+        assert_close(transducer(default_span(), PressureUnit::Mpa).pressure(5.0), 1000.0 * PSI_PER_BAR);
+    }
+
+    #[test]
+    fn pressure_follows_a_custom_current_span() {
+        let transducer = transducer([0.0, 20.0], PressureUnit::Psi);
+
+        // 0, 10 and 20 mA
+        assert_close(transducer.pressure(0.0), 0.0);
+        assert_close(transducer.pressure(2.5), 50.0);
+        assert_close(transducer.pressure(5.0), 100.0);
+    }
+}
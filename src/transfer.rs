@@ -0,0 +1,207 @@
+//! Per-channel transfer functions, from what a channel measures to what is
+//! sent to the flight computer.
+//!
+//! Each reading is first decoded from its ADC code into what the channel
+//! physically measures: volts at the input, ohms for an RTD with a reference
+//! resistor, or kelvin for a thermocouple. The channel's transfer function
+//! then maps that into engineering units. Channels default to the transfer
+//! their hardware implies (e.g. the 11:1 divider ahead of the voltage
+//! channels), and <dir>/transfer.toml overrides them per channel, where <dir>
+//! is the calibration directory (see src/calibration.rs), e.g.
+//!
+//!     version = 3
+//!     date = "2026-10-12"
+//!
+//!     [[channel]]
+//!     measurement = "VValve"
+//!     channel = 2
+//!     transfer = { type = "linear", slope = 11.04, offset = -0.012 }
+//!
+//!     [[channel]]
+//!     measurement = "DiffSensors"
+//!     channel = 0
+//!     transfer = { type = "polynomial", coefficients = [0.0, 1.0, 0.002] }
+//!
+//!     [[channel]]
+//!     measurement = "Rtd"
+//!     channel = 1
+//!     transfer = { type = "table", points = [[100.0, 0.0], [138.5, 100.0]] }
+//!
+//! Polynomial coefficients run from the constant term up. Tables interpolate
+//! linearly between points, and extend their end segments past either end.
+//! Channels with a pressure transducer (see src/transducer.rs) are converted
+//! by it and take no transfer function. The file is read once at startup.
+
+use serde::Deserialize;
+use std::{fs, io, path::{Path, PathBuf}};
+
+use crate::{adc::Measurement, calibration, error::SamError};
+
+const TRANSFER_FILE: &str = "transfer.toml";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Transfer {
+    Linear { slope: f64, offset: f64 },
+    /// Coefficients of x^0, x^1 and so on.
+    Polynomial { coefficients: Vec<f64> },
+    /// (input, output) points, inputs increasing.
+    Table { points: Vec<[f64; 2]> },
+}
+
+impl Transfer {
+    pub const IDENTITY: Transfer = Transfer::Linear { slope: 1.0, offset: 0.0 };
+
+    pub fn apply(&self, x: f64) -> f64 {
+        match self {
+            Transfer::Linear { slope, offset } => slope * x + offset,
+            Transfer::Polynomial { coefficients } => {
+                coefficients.iter().rev().fold(0.0, |sum, coefficient| sum * x + coefficient)
+            }
+            // a table that wasn't validated may be too short to interpolate
+            Transfer::Table { points } if points.len() < 2 => f64::NAN,
+            Transfer::Table { points } => {
+                // the segment x falls in, or the one at the end it is past
+                let segment = points
+                    .windows(2)
+                    .position(|pair| x < pair[1][0])
+                    .unwrap_or(points.len() - 2);
+
+                let [x0, y0] = points[segment];
+                let [x1, y1] = points[segment + 1];
+
+                y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+            }
+        }
+    }
+
+    // why the transfer function can't be applied, if it can't
+    fn problem(&self) -> Option<&'static str> {
+        match self {
+            Transfer::Linear { slope, offset } if !(slope.is_finite() && offset.is_finite()) => {
+                Some("has a slope or offset that isn't a number")
+            }
+            Transfer::Polynomial { coefficients } if coefficients.is_empty() => Some("has no coefficients"),
+            Transfer::Polynomial { coefficients } if !coefficients.iter().all(|c| c.is_finite()) => {
+                Some("has a coefficient that isn't a number")
+            }
+            Transfer::Table { points } if points.len() < 2 => Some("needs at least two points"),
+            Transfer::Table { points } if !points.iter().flatten().all(|value| value.is_finite()) => {
+                Some("has a point that isn't a number")
+            }
+            Transfer::Table { points } if !points.windows(2).all(|pair| pair[0][0] < pair[1][0]) => {
+                Some("has inputs that don't increase")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The transfer function of one channel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelTransfer {
+    pub measurement: Measurement,
+    pub channel: usize,
+    pub transfer: Transfer,
+}
+
+/// Transfer functions overriding the defaults, with the version and date of
+/// the calibration they came from.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransferFile {
+    pub version: u32,
+    pub date: String,
+    #[serde(rename = "channel", default)]
+    pub channels: Vec<ChannelTransfer>,
+}
+
+impl TransferFile {
+    /// Loads the transfer functions in the calibration directory. None when
+    /// there aren't any.
+    pub fn from_env() -> Result<Option<TransferFile>, SamError> {
+        TransferFile::open(calibration::dir_from_env())
+    }
+
+    pub fn open(dir: impl AsRef<Path>) -> Result<Option<TransferFile>, SamError> {
+        let path: PathBuf = dir.as_ref().join(TRANSFER_FILE);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(SamError::Calibration { path: path.display().to_string(), source }),
+        };
+
+        let file: TransferFile = toml::from_str(&contents)
+            .map_err(|source| SamError::CalibrationParse { path: path.display().to_string(), source })?;
+
+        for entry in &file.channels {
+            if let Some(problem) = entry.transfer.problem() {
+                return Err(SamError::InvalidTransfer(format!(
+                    "{:?} channel {} {problem}", entry.measurement, entry.channel
+                )));
+            }
+        }
+
+        Ok(Some(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Transfer {
+        Transfer::Table { points: vec![[0.0, 10.0], [1.0, 20.0], [3.0, 30.0]] }
+    }
+
+    #[test]
+    fn polynomial_coefficients_run_from_the_constant_up() {
+        let transfer = Transfer::Polynomial { coefficients: vec![1.0, 2.0, 3.0] };
+
+        assert_eq!(transfer.apply(0.0), 1.0);
+        assert_eq!(transfer.apply(2.0), 1.0 + 2.0 * 2.0 + 3.0 * 4.0);
+        assert_eq!(transfer.apply(-1.0), 2.0);
+    }
+
+    #[test]
+    fn table_interpolates_between_points() {
+        assert_eq!(table().apply(0.0), 10.0);
+        assert_eq!(table().apply(0.5), 15.0);
+        assert_eq!(table().apply(1.0), 20.0);
+        assert_eq!(table().apply(2.0), 25.0);
+    }
+
+    #[test]
+    fn table_extends_its_end_segments() {
+        assert_eq!(table().apply(-1.0), 0.0);
+        assert_eq!(table().apply(5.0), 40.0);
+    }
+
+    #[test]
+    fn short_tables_have_no_output() {
+        assert!(Transfer::Table { points: vec![[0.0, 1.0]] }.apply(0.0).is_nan());
+        assert!(Transfer::Table { points: Vec::new() }.apply(0.0).is_nan());
+    }
+
+    #[test]
+    fn unusable_transfers_are_rejected() {
+        let unusable = [
+            Transfer::Linear { slope: f64::NAN, offset: 0.0 },
+            Transfer::Polynomial { coefficients: Vec::new() },
+            Transfer::Polynomial { coefficients: vec![1.0, f64::INFINITY] },
+            Transfer::Table { points: vec![[0.0, 0.0]] },
+            Transfer::Table { points: vec![[0.0, 0.0], [1.0, f64::NAN]] },
+            Transfer::Table { points: vec![[0.0, 0.0], [1.0, 1.0], [1.0, 2.0]] },
+            Transfer::Table { points: vec![[1.0, 0.0], [0.0, 1.0]] },
+        ];
+
+        for transfer in unusable {
+            assert!(transfer.problem().is_some(), "{transfer:?}");
+        }
+
+        assert_eq!(Transfer::IDENTITY.problem(), None);
+        assert_eq!(table().problem(), None);
+    }
+}